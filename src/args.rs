@@ -1,41 +1,82 @@
+use std::path::PathBuf;
+
 use clap::{self, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(name = "sony-ctl")]
 #[command(author, version, about = "Sony Headphones CLI", long_about = None)]
-
 pub struct Cli {
+    #[arg(
+        long,
+        global = true,
+        help = "Connect to a sony-sim Unix socket instead of Bluetooth"
+    )]
+    pub socket: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Model behind --socket, asked to the device itself when missing"
+    )]
+    pub model: Option<String>,
+    #[arg(long, global = true, help = "MAC address or name of the device to use")]
+    pub device: Option<String>,
+    #[arg(long, global = true, help = "Bluetooth adapter to use, like hci0")]
+    pub adapter: Option<String>,
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    // #[clap(subcommand)]
-    // Report(Report),
+    #[command(subcommand)]
+    Report(Report),
     #[command(subcommand)]
     Config(Config),
+    #[command(about = "List the supported devices paired with the adapter")]
+    List,
+    #[command(about = "Show the current state of every setting")]
+    Status {
+        #[arg(long, help = "Print the state as a JSON object")]
+        json: bool,
+    },
+    #[command(about = "Print changes notified by the device until interrupted")]
+    Watch {
+        #[arg(long, help = "Print one JSON object per line")]
+        json: bool,
+    },
 }
 
 #[derive(Subcommand)]
 pub enum Report {
-    #[command(subcommand)]
-    // TODO: Add subcommand
+    #[command(about = "Report battery levels")]
     Battery,
-    #[command(subcommand)]
-    // TODO: Add subcommand
+    #[command(about = "Report model, firmware version, series and colour")]
     DeviceInfo,
-    // TODO: Add subcommand
-    #[command(subcommand)]
+    #[command(about = "List the devices the headset is paired with")]
     RegisteredDevices,
+    #[command(about = "List the features supported by the device and its firmware")]
+    Capabilities,
 }
 
 #[derive(Subcommand)]
 pub enum Config {
     #[command(subcommand, about = "Set Ambient Sound Control")]
     ANC(AmbientSoundControl),
+    #[command(
+        subcommand,
+        about = "Toggle automatic switching between NC and ambient sound"
+    )]
+    AutoAnc(Toggle),
+    #[command(about = "Set the modes the NC/AMB gesture cycles through")]
+    OnDeviceAnc {
+        cycle: AncCycle,
+        #[arg(short, long, help = "Use Focus on Voice for ambient sound")]
+        focus_on_voice: bool,
+    },
     #[command(subcommand, about = "Set Equalizer")]
     Eq(EqualizerControl),
+    #[command(about = "Set the connection priority")]
+    ConnectionQuality { mode: ConnectionQuality },
     #[command(subcommand, about = "Toggle DSEE Extreme")]
     DSEE(Toggle),
     #[command(subcommand, about = "Toggle Speak-to-Chat")]
@@ -44,6 +85,15 @@ pub enum Config {
     WearDetection(Toggle),
     #[command(subcommand, about = "Toggle Auto Power Off")]
     AutoPowerOff(Toggle),
+    #[command(subcommand, about = "Toggle Bluetooth multipoint")]
+    Multipoint(Toggle),
+    #[command(about = "Set the function of the touch sensors, a missing side is left as is")]
+    Touch {
+        #[arg(short, long, required_unless_present = "right")]
+        left: Option<TouchFunction>,
+        #[arg(short, long)]
+        right: Option<TouchFunction>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -62,6 +112,8 @@ pub enum AmbientSoundControl {
     },
     #[command(about = "Disable Ambient Sound Control")]
     Off,
+    #[command(about = "Run the noise canceling optimizer")]
+    Optimize,
 }
 
 #[derive(Subcommand)]
@@ -77,6 +129,35 @@ pub enum EqualizerProfile {
     Custom2,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum AncCycle {
+    #[value(help = "Noise Canceling, Ambient Sound, Off")]
+    NcAmbientOff,
+    #[value(help = "Noise Canceling, Ambient Sound")]
+    NcAmbient,
+    #[value(help = "Noise Canceling, Off")]
+    NcOff,
+    #[value(help = "Ambient Sound, Off")]
+    AmbientOff,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ConnectionQuality {
+    #[value(help = "Priority on a stable connection")]
+    Stable,
+    #[value(help = "Priority on sound quality")]
+    Quality,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum TouchFunction {
+    AmbientSoundControl,
+    PlaybackControl,
+    VolumeControl,
+    QuickAccess,
+    NotAssigned,
+}
+
 // #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 // pub enum Bands {
 //     FiveBandsAndBass {
@@ -109,4 +190,17 @@ pub enum EqualizerControl {
         #[arg(value_parser = clap::value_parser!(i8).range(-10..11))]
         b16k: i8,
     },
+    #[command(about = "Set the 10 band equalizer of the XM5 models")]
+    TenBand {
+        profile: EqualizerProfile,
+        #[arg(long, allow_negative_numbers = true, value_parser = clap::value_parser!(i8).range(-10..11))]
+        clear_bass: i8,
+        #[arg(
+            num_args = 10,
+            allow_negative_numbers = true,
+            value_parser = clap::value_parser!(i8).range(-10..11),
+            help = "31, 63, 125, 250, 500, 1k, 2k, 4k, 8k and 16k Hz"
+        )]
+        bands: Vec<i8>,
+    },
 }
@@ -0,0 +1,357 @@
+use std::ops::Range;
+
+use sony_headphone_ctl::devices::wf1000xm4::{
+    anc::WindCode, touch::TouchCode, ApoEnable, CommandTypes,
+};
+
+const MODEL_NAME: &str = "WF-1000XM4";
+const FIRMWARE_VERSION: &str = "2.0.1";
+// Before the DataMdr2 table
+const PROTOCOL_VERSION: u32 = 0x0100_0000;
+
+fn text(command: CommandTypes, text: &str) -> Vec<u8> {
+    let mut bytes = (command as u16).to_be_bytes().to_vec();
+    bytes.push(text.len() as u8);
+    bytes.extend_from_slice(text.as_bytes());
+    bytes
+}
+
+/// What to send back after handling a command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    // Answer to a Get, only for the host that asked
+    Ret(Vec<u8>),
+    // New state after a Set, for every connected host
+    Notify(Vec<u8>),
+}
+
+/// State of the simulated WF-1000XM4, stored the way it goes on the wire.
+#[derive(Debug, Clone)]
+pub struct Headset {
+    pub anc_enable: bool,
+    pub anc_mode: u8,
+    pub nc_wind: u8,
+    pub as_voice: bool,
+    pub as_level: u8,
+    // NC/AMB gesture cycle and Focus on Voice flag
+    pub on_device_anc: [u8; 2],
+    pub eq_preset: u8,
+    pub eq_bands: [u8; 6],
+    pub connection_quality: u8,
+    pub dsee: bool,
+    pub speak_to_chat: bool,
+    pub auto_power_off: ApoEnable,
+    pub pause_on_remove: bool,
+    // None on firmwares without multipoint, which ignore the commands
+    pub multipoint: Option<bool>,
+    // Left and right touch sensor functions
+    pub touch: [u8; 2],
+    // Level and charging flag of the left, right and case batteries
+    pub batteries: [(u8, bool); 3],
+    // MAC, name and connection state of the paired hosts
+    pub registered_devices: Vec<([u8; 6], String, bool)>,
+}
+
+impl Default for Headset {
+    fn default() -> Self {
+        Self {
+            anc_enable: true,
+            anc_mode: 0x00,
+            nc_wind: WindCode::NoWind as u8,
+            as_voice: false,
+            as_level: 0x14,
+            // Noise Canceling, Ambient Sound, Off
+            on_device_anc: [0x01, 0x00],
+            eq_preset: 0x00,
+            // +0dB on every band
+            eq_bands: [0x0a; 6],
+            // Priority on sound quality
+            connection_quality: 0x00,
+            dsee: false,
+            speak_to_chat: false,
+            auto_power_off: ApoEnable::On,
+            pause_on_remove: true,
+            multipoint: Some(false),
+            touch: [
+                TouchCode::AmbientSoundControl as u8,
+                TouchCode::PlaybackControl as u8,
+            ],
+            batteries: [(80, false), (70, false), (50, true)],
+            registered_devices: vec![
+                (
+                    [0x00, 0x11, 0x22, 0x33, 0x44, 0x55],
+                    "sony-sim host".to_string(),
+                    true,
+                ),
+                (
+                    [0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb],
+                    "Phone".to_string(),
+                    false,
+                ),
+            ],
+        }
+    }
+}
+
+impl Headset {
+    /// Applies a `DataMdr` payload and returns the payload to send back, if any.
+    ///
+    /// Malformed or unknown commands leave the state untouched.
+    pub fn handle(&mut self, payload: &[u8]) -> Option<Reply> {
+        if payload.len() < 2 {
+            return None;
+        }
+        let command = CommandTypes::try_from(u16::from_be_bytes([payload[0], payload[1]])).ok()?;
+        let args = &payload[2..];
+
+        match command {
+            CommandTypes::ProtocolInfoGet => {
+                let mut bytes = (CommandTypes::ProtocolInfoRet as u16)
+                    .to_be_bytes()
+                    .to_vec();
+                bytes.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+                Some(Reply::Ret(bytes))
+            }
+            CommandTypes::ModelNameGet => {
+                Some(Reply::Ret(text(CommandTypes::ModelNameRet, MODEL_NAME)))
+            }
+            CommandTypes::FirmwareVersionGet => Some(Reply::Ret(text(
+                CommandTypes::FirmwareVersionRet,
+                FIRMWARE_VERSION,
+            ))),
+            CommandTypes::SeriesColorGet => {
+                let mut bytes = (CommandTypes::SeriesColorRet as u16).to_be_bytes().to_vec();
+                // Premium series, black
+                bytes.extend_from_slice(&[0x40, 0x01]);
+                Some(Reply::Ret(bytes))
+            }
+            CommandTypes::RegisteredDevicesGet => Some(Reply::Ret(self.registered_devices())),
+            CommandTypes::BatteryGet => {
+                Some(Reply::Ret(self.battery(CommandTypes::BatteryRet, 0..2)))
+            }
+            CommandTypes::CaseBatteryGet => {
+                Some(Reply::Ret(self.battery(CommandTypes::CaseBatteryRet, 2..3)))
+            }
+            CommandTypes::AncGet => Some(Reply::Ret(self.anc(CommandTypes::AncRet))),
+            CommandTypes::AncSet if args.len() == 6 => {
+                self.anc_enable = args[1] != 0;
+                self.anc_mode = args[2];
+                self.nc_wind = args[3];
+                self.as_voice = args[4] != 0;
+                self.as_level = args[5];
+                Some(Reply::Notify(self.anc(CommandTypes::AncAck)))
+            }
+            CommandTypes::OnDeviceAncGet => {
+                Some(Reply::Ret(self.on_device_anc(CommandTypes::OnDeviceAncRet)))
+            }
+            CommandTypes::OnDeviceAncSet if args.len() == 2 => {
+                self.on_device_anc.copy_from_slice(args);
+                Some(Reply::Notify(
+                    self.on_device_anc(CommandTypes::OnDeviceAncAck),
+                ))
+            }
+            CommandTypes::EqGet => Some(Reply::Ret(self.equalizer(CommandTypes::EqRet))),
+            CommandTypes::EqSet if args.len() >= 2 => {
+                self.eq_preset = args[0];
+                if args[1] == 6 && args.len() == 8 {
+                    self.eq_bands.copy_from_slice(&args[2..8]);
+                }
+                Some(Reply::Notify(self.equalizer(CommandTypes::EqAck)))
+            }
+            CommandTypes::ConnectionQualityGet => Some(Reply::Ret(
+                self.connection_quality(CommandTypes::ConnectionQualityRet),
+            )),
+            CommandTypes::ConnectionQualitySet if args.len() == 1 => {
+                self.connection_quality = args[0];
+                Some(Reply::Notify(
+                    self.connection_quality(CommandTypes::ConnectionQualityAck),
+                ))
+            }
+            CommandTypes::DseeGet => Some(Reply::Ret(self.dsee(CommandTypes::DseeRet))),
+            CommandTypes::DseeSet if args.len() == 1 => {
+                self.dsee = args[0] != 0;
+                Some(Reply::Notify(self.dsee(CommandTypes::DseeAck)))
+            }
+            CommandTypes::StcGet => Some(Reply::Ret(self.speak_to_chat(CommandTypes::StcRet))),
+            CommandTypes::StcSet if args.len() == 2 => {
+                self.speak_to_chat = args[0] == 0;
+                Some(Reply::Notify(self.speak_to_chat(CommandTypes::StcAck)))
+            }
+            CommandTypes::AutoPowerOffGet => Some(Reply::Ret(
+                self.auto_power_off(CommandTypes::AutoPowerOffRet),
+            )),
+            CommandTypes::AutoPowerOffSet if args.len() == 2 => {
+                self.auto_power_off = ApoEnable::try_from(args[0]).ok()?;
+                Some(Reply::Notify(
+                    self.auto_power_off(CommandTypes::AutoPowerOffAck),
+                ))
+            }
+            CommandTypes::PauseRemovedGet => Some(Reply::Ret(
+                self.pause_on_remove(CommandTypes::PauseRemovedRet),
+            )),
+            CommandTypes::PauseRemovedSet if args.len() == 1 => {
+                self.pause_on_remove = args[0] == 0;
+                Some(Reply::Notify(
+                    self.pause_on_remove(CommandTypes::PauseRemovedAck),
+                ))
+            }
+            CommandTypes::MultipointGet => {
+                Some(Reply::Ret(self.multipoint(CommandTypes::MultipointRet)?))
+            }
+            CommandTypes::MultipointSet if args.len() == 1 => {
+                self.multipoint.as_ref()?;
+                self.multipoint = Some(args[0] == 0);
+                Some(Reply::Notify(self.multipoint(CommandTypes::MultipointAck)?))
+            }
+            CommandTypes::TouchGet => Some(Reply::Ret(self.touch(CommandTypes::TouchRet))),
+            CommandTypes::TouchSet if args.len() == 3 && args[0] == 0x02 => {
+                self.touch.copy_from_slice(&args[1..3]);
+                Some(Reply::Notify(self.touch(CommandTypes::TouchAck)))
+            }
+            _ => None,
+        }
+    }
+
+    fn battery(&self, command: CommandTypes, batteries: Range<usize>) -> Vec<u8> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        for (level, charging) in &self.batteries[batteries] {
+            bytes.push(*level);
+            bytes.push(*charging as u8);
+        }
+        bytes
+    }
+
+    fn registered_devices(&self) -> Vec<u8> {
+        let mut bytes = (CommandTypes::RegisteredDevicesRet as u16)
+            .to_be_bytes()
+            .to_vec();
+        bytes.push(self.registered_devices.len() as u8);
+        for (mac, name, connected) in &self.registered_devices {
+            bytes.extend_from_slice(mac);
+            bytes.push(*connected as u8);
+            bytes.push(name.len() as u8);
+            bytes.extend_from_slice(name.as_bytes());
+        }
+        bytes
+    }
+
+    fn anc(&self, command: CommandTypes) -> Vec<u8> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[
+            0x01,
+            self.anc_enable as u8,
+            self.anc_mode,
+            self.nc_wind,
+            self.as_voice as u8,
+            self.as_level,
+        ]);
+        bytes
+    }
+
+    fn on_device_anc(&self, command: CommandTypes) -> Vec<u8> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.on_device_anc);
+        bytes
+    }
+
+    fn equalizer(&self, command: CommandTypes) -> Vec<u8> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        bytes.push(self.eq_preset);
+        bytes.push(self.eq_bands.len() as u8);
+        bytes.extend_from_slice(&self.eq_bands);
+        bytes
+    }
+
+    fn connection_quality(&self, command: CommandTypes) -> Vec<u8> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        bytes.push(self.connection_quality);
+        bytes
+    }
+
+    fn dsee(&self, command: CommandTypes) -> Vec<u8> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        bytes.push(self.dsee as u8);
+        bytes
+    }
+
+    fn speak_to_chat(&self, command: CommandTypes) -> Vec<u8> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        bytes.push(!self.speak_to_chat as u8);
+        bytes.push(0x01);
+        bytes
+    }
+
+    fn auto_power_off(&self, command: CommandTypes) -> Vec<u8> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        bytes.push(self.auto_power_off as u8);
+        bytes.push(0x00);
+        bytes
+    }
+
+    fn pause_on_remove(&self, command: CommandTypes) -> Vec<u8> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        bytes.push(!self.pause_on_remove as u8);
+        bytes
+    }
+
+    fn multipoint(&self, command: CommandTypes) -> Option<Vec<u8>> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        bytes.push(!self.multipoint? as u8);
+        Some(bytes)
+    }
+
+    fn touch(&self, command: CommandTypes) -> Vec<u8> {
+        let mut bytes = (command as u16).to_be_bytes().to_vec();
+        bytes.push(0x02);
+        bytes.extend_from_slice(&self.touch);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_anc_notifies_new_state() {
+        let mut headset = Headset::default();
+        let reply = headset.handle(&[0x68, 0x15, 0x01, 0x01, 0x01, 0x02, 0x01, 0x05]);
+
+        assert_eq!(
+            reply,
+            Some(Reply::Notify(vec![
+                0x69, 0x15, 0x01, 0x01, 0x01, 0x02, 0x01, 0x05
+            ]))
+        );
+        assert_eq!(
+            headset.handle(&[0x66, 0x15]),
+            Some(Reply::Ret(vec![
+                0x67, 0x15, 0x01, 0x01, 0x01, 0x02, 0x01, 0x05
+            ]))
+        );
+    }
+
+    #[test]
+    fn set_equalizer_profile_keeps_bands() {
+        let mut headset = Headset::default();
+        headset.handle(&[0x58, 0x00, 0xa1, 0x06, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05]);
+        let reply = headset.handle(&[0x58, 0x00, 0xa2, 0x01, 0x00]);
+
+        assert_eq!(
+            reply,
+            Some(Reply::Notify(vec![
+                0x59, 0x00, 0xa2, 0x06, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05
+            ]))
+        );
+    }
+
+    #[test]
+    fn ignores_malformed_commands() {
+        let mut headset = Headset::default();
+
+        assert_eq!(headset.handle(&[0x68, 0x15, 0x01]), None);
+        assert_eq!(headset.handle(&[0x12, 0x34, 0x01]), None);
+        assert_eq!(headset.handle(&[0x68]), None);
+        assert!(headset.anc_enable);
+    }
+}
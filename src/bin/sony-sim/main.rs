@@ -0,0 +1,151 @@
+mod headset;
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use clap::Parser;
+use sony_headphone_ctl::{decoder::FrameDecoder, DataType, Error, SonyCommand};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::broadcast,
+};
+
+use headset::{Headset, Reply};
+
+#[derive(Parser)]
+#[command(name = "sony-sim")]
+#[command(author, version, about = "Simulated WF-1000XM4 for testing without a headset", long_about = None)]
+struct Cli {
+    #[arg(short, long, default_value = "/tmp/sony-sim.sock")]
+    socket: PathBuf,
+    #[arg(long, help = "Behave like a firmware without Bluetooth multipoint")]
+    no_multipoint: bool,
+}
+
+async fn write_command(
+    stream: &mut UnixStream,
+    data_type: DataType,
+    seq_number: u32,
+    payload: Vec<u8>,
+) -> Result<(), Error> {
+    let raw: Vec<u8> = SonyCommand {
+        data_type,
+        seq_number,
+        payload_size: payload.len() as u8,
+        payload,
+        checksum: 0,
+    }
+    .try_into()?;
+    stream
+        .write_all(&raw)
+        .await
+        .map_err(|x| Error::Io(x.to_string()))
+}
+
+async fn serve(
+    mut stream: UnixStream,
+    headset: Arc<Mutex<Headset>>,
+    notifications: broadcast::Sender<Vec<u8>>,
+) -> Result<(), Error> {
+    let mut decoder = FrameDecoder::new();
+    let mut buffer = vec![0; 1024];
+    let mut seq_number = 0;
+    let mut last_received = None;
+    let mut pending_notifications = notifications.subscribe();
+
+    loop {
+        let len = tokio::select! {
+            len = stream.read(&mut buffer) => len.map_err(|x| Error::Io(x.to_string()))?,
+            notification = pending_notifications.recv() => {
+                // A client too slow to keep up just misses some
+                if let Ok(notification) = notification {
+                    println!("-> {}", hex::encode(&notification));
+                    write_command(&mut stream, DataType::DataMdr, seq_number, notification)
+                        .await?;
+                    seq_number = 1 - seq_number;
+                }
+                continue;
+            }
+        };
+        if len == 0 {
+            return Ok(());
+        }
+        decoder.push(&buffer[..len]);
+
+        while let Some(command) = decoder.next_command() {
+            let command = match command {
+                Ok(command) => command,
+                Err(e) => {
+                    println!("!! {}", e);
+                    continue;
+                }
+            };
+            if command.data_type == DataType::Ack {
+                continue;
+            }
+            println!("<- {}", hex::encode(&command.payload));
+
+            // The ACK carries the sequence number expected next
+            write_command(
+                &mut stream,
+                DataType::Ack,
+                1 - command.seq_number % 2,
+                vec![],
+            )
+            .await?;
+
+            // Our ACK got lost and the host sent the command again
+            if last_received == Some(command.seq_number) {
+                continue;
+            }
+            last_received = Some(command.seq_number);
+
+            let reply = headset.lock().unwrap().handle(&command.payload);
+            match reply {
+                Some(Reply::Ret(reply)) => {
+                    println!("-> {}", hex::encode(&reply));
+                    write_command(&mut stream, DataType::DataMdr, seq_number, reply).await?;
+                    seq_number = 1 - seq_number;
+                }
+                // Every client hears about the change, this one included
+                Some(Reply::Notify(reply)) => {
+                    let _ = notifications.send(reply);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let args = Cli::parse();
+
+    // A socket left behind by a previous run would make bind fail
+    let _ = std::fs::remove_file(&args.socket);
+    let listener = UnixListener::bind(&args.socket)?;
+    println!("Listening on {}", args.socket.display());
+
+    // State is shared so settings survive reconnects, like on the real earbuds
+    let mut headset = Headset::default();
+    if args.no_multipoint {
+        headset.multipoint = None;
+    }
+    let headset = Arc::new(Mutex::new(headset));
+    let (notifications, _) = broadcast::channel(32);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let headset = headset.clone();
+        let notifications = notifications.clone();
+        tokio::spawn(async move {
+            println!("Client connected");
+            if let Err(e) = serve(stream, headset, notifications).await {
+                println!("!! {}", e);
+            }
+            println!("Client disconnected");
+        });
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{Error, SonyCommand, END_MARKER, START_MARKER};
+
+/// Largest frame we are willing to buffer while waiting for an end marker.
+/// Anything bigger can only be garbage, so the decoder resyncs past it.
+const MAX_FRAME_SIZE: usize = 1024;
+
+/// Stateful decoder turning a raw byte stream into `SonyCommand`s.
+///
+/// Bytes are fed with [`FrameDecoder::push`] as they come off the wire, in
+/// chunks of any size, and complete frames are pulled out in order with
+/// [`FrameDecoder::next_command`].
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete frame, or `None` if more bytes are needed.
+    ///
+    /// A frame that fails to parse is dropped from the buffer and returned as
+    /// an error, so calling this again picks up with the following frame.
+    pub fn next_command(&mut self) -> Option<Result<SonyCommand, Error>> {
+        loop {
+            // Drop everything before the start of the next frame
+            match self.buffer.iter().position(|b| *b == START_MARKER) {
+                Some(start) => {
+                    self.buffer.drain(..start);
+                }
+                None => {
+                    self.buffer.clear();
+                    return None;
+                }
+            }
+
+            let end = match self.buffer.iter().position(|b| *b == END_MARKER) {
+                Some(end) => end,
+                None => {
+                    if self.buffer.len() > MAX_FRAME_SIZE {
+                        self.buffer.drain(..1);
+                        continue;
+                    }
+                    return None;
+                }
+            };
+
+            // Another start marker before the end one means the previous frame
+            // was truncated, resync on the latest one.
+            if let Some(restart) = self.buffer[1..end].iter().rposition(|b| *b == START_MARKER) {
+                self.buffer.drain(..restart + 1);
+                continue;
+            }
+
+            let frame: Vec<u8> = self.buffer.drain(..=end).collect();
+            return Some(SonyCommand::try_from(frame.as_slice()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+
+    fn command(seq_number: u32, payload: &[u8]) -> SonyCommand {
+        SonyCommand {
+            data_type: DataType::DataMdr,
+            seq_number,
+            payload_size: payload.len() as u8,
+            payload: payload.to_vec(),
+            checksum: 0,
+        }
+    }
+
+    fn frame(seq_number: u32, payload: &[u8]) -> Vec<u8> {
+        command(seq_number, payload).try_into().unwrap()
+    }
+
+    fn drain(decoder: &mut FrameDecoder) -> Vec<SonyCommand> {
+        let mut commands = vec![];
+        while let Some(command) = decoder.next_command() {
+            if let Ok(command) = command {
+                commands.push(command);
+            }
+        }
+        commands
+    }
+
+    fn payloads(commands: &[SonyCommand]) -> Vec<Vec<u8>> {
+        commands.iter().map(|c| c.payload.clone()).collect()
+    }
+
+    #[test]
+    fn decodes_single_frame() {
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&frame(1, &[0x69, 0x15, 0x01]));
+
+        let command = decoder.next_command().unwrap().unwrap();
+        assert_eq!(command.data_type, DataType::DataMdr);
+        assert_eq!(command.seq_number, 1);
+        assert_eq!(command.payload, vec![0x69, 0x15, 0x01]);
+        assert!(decoder.next_command().is_none());
+    }
+
+    #[test]
+    fn decodes_byte_by_byte() {
+        let mut decoder = FrameDecoder::new();
+        let mut commands = vec![];
+        for byte in frame(0, &[0x58, 0x00, 0xa1, 0x00]) {
+            decoder.push(&[byte]);
+            commands.extend(drain(&mut decoder));
+        }
+
+        assert_eq!(payloads(&commands), vec![vec![0x58, 0x00, 0xa1, 0x00]]);
+    }
+
+    #[test]
+    fn decodes_several_frames_in_one_read() {
+        let mut bytes = frame(0, &[]);
+        bytes.extend(frame(1, &[0x69, 0x15]));
+        bytes.extend(frame(0, &[0x59, 0x00]));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&bytes);
+
+        assert_eq!(
+            payloads(&drain(&mut decoder)),
+            vec![vec![], vec![0x69, 0x15], vec![0x59, 0x00]]
+        );
+    }
+
+    #[test]
+    fn resyncs_after_garbage() {
+        let mut bytes = vec![0x00, 0xff, 0x12];
+        bytes.extend(frame(0, &[0x01]));
+        bytes.extend([0x55, END_MARKER, 0x42]);
+        bytes.extend(frame(1, &[0x02]));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&bytes);
+
+        assert_eq!(payloads(&drain(&mut decoder)), vec![vec![0x01], vec![0x02]]);
+    }
+
+    #[test]
+    fn skips_truncated_frame() {
+        let truncated = frame(0, &[0x01, 0x02, 0x03]);
+        let mut bytes = truncated[..5].to_vec();
+        bytes.extend(frame(1, &[0x04]));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&bytes);
+
+        assert_eq!(payloads(&drain(&mut decoder)), vec![vec![0x04]]);
+    }
+
+    #[test]
+    fn reports_bad_checksum_and_continues() {
+        let mut corrupted = frame(0, &[0x01]);
+        let checksum = corrupted.len() - 2;
+        corrupted[checksum] = corrupted[checksum].wrapping_add(1);
+        corrupted.extend(frame(1, &[0x02]));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&corrupted);
+
+        assert!(matches!(
+            decoder.next_command().unwrap(),
+            Err(Error::Checksum { .. })
+        ));
+        assert_eq!(decoder.next_command().unwrap().unwrap().payload, vec![0x02]);
+        assert!(decoder.next_command().is_none());
+    }
+
+    #[test]
+    fn decodes_random_chunkings() {
+        // Checksums and payloads run through every marker and escape byte
+        let expected: Vec<Vec<u8>> = (0..40u8)
+            .map(|i| (0..i).map(|b| 0x3a + (b + i) % 5).collect())
+            .collect();
+        let mut bytes = vec![];
+        for (i, payload) in expected.iter().enumerate() {
+            bytes.extend(frame(i as u32 % 2, payload));
+        }
+
+        // Small xorshift generator, deterministic so failures are reproducible
+        let mut state: u32 = 0x1234_5678;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        for _ in 0..200 {
+            let mut decoder = FrameDecoder::new();
+            let mut commands = vec![];
+            let mut rest = bytes.as_slice();
+            while !rest.is_empty() {
+                let len = (next() as usize % 32 + 1).min(rest.len());
+                decoder.push(&rest[..len]);
+                rest = &rest[len..];
+                commands.extend(drain(&mut decoder));
+            }
+            assert_eq!(payloads(&commands), expected);
+        }
+    }
+}
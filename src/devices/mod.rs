@@ -1,204 +1,870 @@
-use std::{fmt::Debug, time::Duration};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
-use bluer::{rfcomm::Stream, Address};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    time::timeout,
+use async_trait::async_trait;
+use bluer::{
+    rfcomm::{Profile, ProfileHandle, Role, Stream},
+    Adapter, Address, Device, Session, Uuid,
+};
+use futures::{
+    future::BoxFuture,
+    stream::{self, BoxStream, StreamExt},
 };
+use serde::{Serialize, Serializer};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
-use crate::{DataType, Error, SonyCommand};
+use crate::{transport::Transport, Error, Mac, SonyCommand};
 
 pub mod wf1000xm4;
+pub mod wf1000xm5;
+pub mod wh1000xm4;
+pub mod wh1000xm5;
 
-pub const SONY_DEVICES: &[&str] = &["WF-1000XM4"];
+pub type DeviceFuture = BoxFuture<'static, Result<Box<dyn SonyDevice>, Error>>;
 
-pub trait DeviceCommand
-where
-    Self: Sized + Clone + Debug + TryInto<SonyCommand, Error = Error>,
-{
+/// Model this crate knows how to drive.
+#[derive(Debug)]
+pub struct DeviceModel {
+    /// Name the device advertises over Bluetooth.
+    pub name: &'static str,
+    /// Connects to the device at a Bluetooth address.
+    pub connect: fn(Address) -> DeviceFuture,
+    /// Drives the device over an already established transport.
+    pub with_transport: fn(Box<dyn Transport>) -> Box<dyn SonyDevice>,
 }
 
-pub trait SonyDevice
-where
-    Self: Sized + Debug,
-{
-    async fn new(mac: Address) -> Result<Self, Error>;
-
-    async fn send_command<C: DeviceCommand>(stream: &mut Stream, command: C) -> Result<(), Error> {
-        let command: SonyCommand = command.try_into()?;
-        // println!("Sending {:?}", command);
-        let raw_command: Vec<u8> = command.try_into()?;
-        // println!("Sending raw {:?}", hex::encode(&raw_command));
-        stream
-            .write_all(raw_command.as_slice())
-            .await
-            .map_err(|x| Error::new(x.to_string()))?;
-        Ok(())
+/// Every supported model, adding one here makes it usable from the cli.
+pub const MODELS: &[DeviceModel] = &[
+    wf1000xm4::MODEL,
+    wh1000xm4::MODEL,
+    wf1000xm5::MODEL,
+    wh1000xm5::MODEL,
+];
+
+/// Looks up a model by the name it advertises.
+pub fn find_model(name: &str) -> Option<&'static DeviceModel> {
+    MODELS.iter().find(|model| model.name == name)
+}
+
+/// RFCOMM services the headsets are driven over, the second one on XM5 models.
+pub const SONY_SERVICE_UUIDS: [Uuid; 2] = [
+    Uuid::from_u128(0x96cc203e_5068_46ad_9b20_fe8e82e6a5a7),
+    Uuid::from_u128(0x956c7b26_d49a_4ba8_b03f_b17d393cb6e2),
+];
+
+/// RFCOMM connection to the Sony service of a device, see [`connect_service`].
+///
+/// Keeps the profile registered with BlueZ for as long as the connection is
+/// used, unregistering it closes the connection.
+pub struct ServiceStream {
+    stream: Stream,
+    _profile: ProfileHandle,
+    _session: Session,
+}
+
+impl Debug for ServiceStream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceStream")
+            .field("stream", &self.stream)
+            .finish_non_exhaustive()
     }
+}
 
-    async fn send_ack(stream: &mut Stream) -> Result<(), Error> {
-        let ack = SonyCommand {
-            data_type: DataType::Ack,
-            seq_number: 0,
-            payload_size: 0,
-            payload: vec![],
-            checksum: 0,
-        };
-        let raw_ack: Vec<u8> = ack.try_into()?;
+impl AsyncRead for ServiceStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
 
-        stream
-            .write_all(&raw_ack.as_slice())
-            .await
-            .map_err(|x| Error::new(x.to_string()))?;
+impl AsyncWrite for ServiceStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
 
-        Ok(())
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
     }
 
-    async fn read(stream: &mut Stream) -> Result<SonyCommand, Error> {
-        let mut buffer = vec![0; 1024];
-        let len = stream
-            .read(&mut buffer)
-            .await
-            .map_err(|x| Error::new(x.to_string()))?;
-
-        // print!("Received {:?} bytes:  | {:?}", len, &buffer[0..len]);
-        let command = SonyCommand::try_from(&buffer[0..len])?;
-        Ok(command)
-    }
-
-    async fn wait_ack(stream: &mut Stream) -> Result<(), Error> {
-        match timeout(Duration::from_secs(1), Self::read(stream)).await {
-            Ok(res) => {
-                let cmd = res?;
-                match cmd.data_type {
-                    DataType::Ack => return Ok(()),
-                    _ => {
-                        return Err(Error::new(format!("Invalid Ack received: {:?}", cmd)));
-                    }
-                }
-            }
-            Err(_) => Err(Error::new("No Ack received".to_string())),
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Connects to the Sony service of the device at `address`.
+///
+/// The RFCOMM channel is not the same on every model, so BlueZ resolves it
+/// from the SDP record of the service. The XM5 service is preferred when the
+/// device offers both.
+pub async fn connect_service(address: Address) -> Result<ServiceStream, Error> {
+    let session = Session::new().await.map_err(bluetooth_error)?;
+    let bt_device = paired_with(&session, address).await?;
+    let uuids = bt_device
+        .uuids()
+        .await
+        .map_err(bluetooth_error)?
+        .unwrap_or_default();
+    let uuid = *SONY_SERVICE_UUIDS
+        .iter()
+        .rev()
+        .find(|uuid| uuids.contains(uuid))
+        .ok_or_else(|| {
+            Error::DeviceNotFound(format!("{} does not offer the Sony service", address))
+        })?;
+
+    let mut profile = session
+        .register_profile(Profile {
+            uuid,
+            role: Some(Role::Client),
+            require_authentication: Some(false),
+            require_authorization: Some(false),
+            auto_connect: Some(false),
+            ..Default::default()
+        })
+        .await
+        .map_err(bluetooth_error)?;
+    // BlueZ hands the connection to the profile before the call returns
+    let (connected, request) = tokio::join!(bt_device.connect_profile(&uuid), profile.next());
+    connected.map_err(bluetooth_error)?;
+    let stream = request
+        .ok_or_else(|| Error::Io(format!("No connection to {} was made", address)))?
+        .accept()
+        .map_err(bluetooth_error)?;
+
+    Ok(ServiceStream {
+        stream,
+        _profile: profile,
+        _session: session,
+    })
+}
+
+// The device is looked up on every adapter, the one it is paired with is not known
+async fn paired_with(session: &Session, address: Address) -> Result<Device, Error> {
+    for name in session.adapter_names().await.map_err(bluetooth_error)? {
+        let adapter = session.adapter(&name).map_err(bluetooth_error)?;
+        let addresses = adapter.device_addresses().await.map_err(bluetooth_error)?;
+        if addresses.contains(&address) {
+            return adapter.device(address).map_err(bluetooth_error);
+        }
+    }
+    Err(Error::DeviceNotFound(format!(
+        "{} is not paired with any adapter",
+        address
+    )))
+}
+
+fn bluetooth_error(error: bluer::Error) -> Error {
+    Error::Io(error.to_string())
+}
+
+/// Asks the device behind `transport` for its model.
+///
+/// Fails when the device does not answer the handshake, and returns `None`
+/// for a device speaking the protocol but unknown to this tool.
+pub async fn identify(
+    transport: impl Transport + 'static,
+) -> Result<Option<&'static DeviceModel>, Error> {
+    identify_device(&mut wf1000xm4::Wf1000xm4::with_transport(transport)).await
+}
+
+// Every model answers the handshake and device info from the `DataMdr` table
+async fn identify_device(
+    device: &mut wf1000xm4::Wf1000xm4,
+) -> Result<Option<&'static DeviceModel>, Error> {
+    device.get_protocol_info().await?;
+    let info = device.get_device_info().await?;
+    Ok(find_model(&info.model_name))
+}
+
+/// Asks the device at `address` for its model, over the Sony service.
+///
+/// Returns `None` for a device speaking the protocol but unknown to this tool.
+pub async fn identify_address(address: Address) -> Result<Option<&'static DeviceModel>, Error> {
+    let mut device = wf1000xm4::Wf1000xm4::new(address).await?;
+    identify_device(&mut device).await
+}
+
+/// Finds the model of the device at `address`, paired with `adapter`.
+///
+/// Connected devices offering the Sony service are asked for their model, so
+/// renamed devices are still recognized. The advertised name, then the alias,
+/// are used when that fails.
+pub async fn detect_model(
+    adapter: &Adapter,
+    address: Address,
+) -> Result<&'static DeviceModel, Error> {
+    let bt_device = adapter
+        .device(address)
+        .map_err(|x| Error::Io(x.to_string()))?;
+
+    // Connecting to a device out of range only ends with a long timeout
+    let connected = bt_device.is_connected().await.unwrap_or(false);
+    if connected && offers_sony_service(&bt_device).await {
+        if let Ok(Some(model)) = identify_address(address).await {
+            return Ok(model);
+        }
+    }
+
+    model_from_name(&bt_device)
+        .await
+        .ok_or_else(|| Error::DeviceNotFound(format!("{} is not a supported Sony device", address)))
+}
+
+async fn model_from_name(bt_device: &Device) -> Option<&'static DeviceModel> {
+    let name = bt_device.name().await.ok().flatten();
+    let alias = bt_device.alias().await.ok();
+    [name, alias]
+        .iter()
+        .flatten()
+        .find_map(|name| find_model(name))
+}
+
+async fn offers_sony_service(bt_device: &Device) -> bool {
+    let uuids = bt_device.uuids().await.ok().flatten().unwrap_or_default();
+    SONY_SERVICE_UUIDS.iter().any(|uuid| uuids.contains(uuid))
+}
+
+/// Supported device paired with an adapter.
+#[derive(Debug, Clone)]
+pub struct PairedDevice {
+    pub address: Address,
+    pub alias: String,
+    // Unknown when the name does not tell it, asked to the device on connect
+    pub model: Option<&'static DeviceModel>,
+    pub connected: bool,
+}
+
+/// Connects to a paired device.
+///
+/// Devices whose model is not known from their name are asked for it with
+/// the handshake first, see [`identify_address`], on a connection closed
+/// before the real one is made.
+pub async fn connect(device: &PairedDevice) -> Result<Box<dyn SonyDevice>, Error> {
+    let model = match device.model {
+        Some(model) => model,
+        None => identify_address(device.address).await?.ok_or_else(|| {
+            Error::DeviceNotFound(format!("{} is not a supported Sony device", device.address))
+        })?,
+    };
+    (model.connect)(device.address).await
+}
+
+/// Every supported device paired with `adapter`.
+///
+/// Devices are listed from their name and the services they offer, nothing
+/// is sent to them. Use [`detect_model`] to ask a device for its model.
+pub async fn paired_devices(adapter: &Adapter) -> Result<Vec<PairedDevice>, Error> {
+    let addresses = adapter
+        .device_addresses()
+        .await
+        .map_err(|x| Error::Io(x.to_string()))?;
+
+    let mut devices = vec![];
+    for address in addresses {
+        let bt_device = adapter
+            .device(address)
+            .map_err(|x| Error::Io(x.to_string()))?;
+        let model = model_from_name(&bt_device).await;
+        if model.is_none() && !offers_sony_service(&bt_device).await {
+            continue;
         }
+        devices.push(PairedDevice {
+            address,
+            alias: bt_device.alias().await.unwrap_or_default(),
+            model,
+            connected: bt_device.is_connected().await.unwrap_or(false),
+        });
     }
+    Ok(devices)
+}
+
+pub trait DeviceCommand
+where
+    Self: Sized + Clone + Debug + TryInto<SonyCommand, Error = Error>,
+{
+}
 
-    async fn send_with_ack<C: DeviceCommand>(stream: &mut Stream, command: C) -> Result<(), Error> {
-        for _ in 0..3 {
-            Self::send_command(stream, command.clone()).await?;
-            match Self::wait_ack(stream).await {
-                Ok(_) => return Ok(()),
-                Err(_) => {}
+fn unsupported(feature: &str) -> Error {
+    Error::Unsupported(format!("{} is not supported by this device", feature))
+}
+
+#[async_trait]
+pub trait SonyDevice: Debug + Send {
+    async fn new(mac: Address) -> Result<Self, Error>
+    where
+        Self: Sized;
+
+    /// Whether `feature` is supported by this device and its firmware.
+    async fn supports(&mut self, _feature: Feature) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    /// Every feature supported by this device and its firmware.
+    async fn capabilities(&mut self) -> Result<Capabilities, Error> {
+        let mut features = vec![];
+        for feature in Feature::ALL {
+            if self.supports(feature).await? {
+                features.push(feature);
             }
         }
-        Self::send_command(stream, command).await?;
-        Self::send_ack(stream).await?;
-        Ok(())
+        Ok(Capabilities { features })
     }
 
-    async fn get_device_info(&self) -> Result<DeviceInfo, Error> {
-        unimplemented!()
+    async fn get_protocol_info(&mut self) -> Result<ProtocolInfo, Error> {
+        Err(unsupported("Protocol info"))
+    }
+    async fn get_device_info(&mut self) -> Result<DeviceInfo, Error> {
+        Err(unsupported("Device info"))
     }
-    async fn get_battery_info(&self) -> Result<BatteryInfo, Error> {
-        unimplemented!()
+    async fn get_battery_info(&mut self) -> Result<BatteryInfo, Error> {
+        Err(unsupported("Battery"))
     }
-    async fn get_registered_devices(&self) -> Result<RegisteredDevices, Error> {
-        unimplemented!()
+    async fn get_registered_devices(&mut self) -> Result<RegisteredDevices, Error> {
+        Err(unsupported("Registered devices"))
     }
 
     async fn set_anc(&mut self, _anc: Anc) -> Result<(), Error> {
-        unimplemented!()
+        Err(unsupported("ANC"))
     }
     async fn get_anc(&mut self) -> Result<Anc, Error> {
-        unimplemented!()
+        Err(unsupported("ANC"))
+    }
+
+    /// Runs the noise canceling optimizer, which adapts to the wearer and
+    /// the atmospheric pressure.
+    async fn optimize_anc(&mut self) -> Result<(), Error> {
+        Err(unsupported("NC optimizer"))
+    }
+
+    /// Lets the device switch between noise canceling and ambient sound by
+    /// itself, depending on the surroundings.
+    async fn set_auto_anc(&mut self, _auto_anc: bool) -> Result<(), Error> {
+        Err(unsupported("Auto ANC"))
+    }
+    async fn get_auto_anc(&mut self) -> Result<bool, Error> {
+        Err(unsupported("Auto ANC"))
     }
 
     async fn set_equalizer(&mut self, _equalizer: Equalizer) -> Result<(), Error> {
-        unimplemented!()
+        Err(unsupported("Equalizer"))
     }
-    async fn get_equalizer(&self) -> Result<Equalizer, Error> {
-        unimplemented!()
+    async fn get_equalizer(&mut self) -> Result<Equalizer, Error> {
+        Err(unsupported("Equalizer"))
     }
 
     async fn set_connection_quality(
         &mut self,
         _connection_quality: ConnectionQuality,
     ) -> Result<(), Error> {
-        unimplemented!()
+        Err(unsupported("Connection quality"))
     }
-    async fn get_connection_quality(&self) -> Result<ConnectionQuality, Error> {
-        unimplemented!()
+    async fn get_connection_quality(&mut self) -> Result<ConnectionQuality, Error> {
+        Err(unsupported("Connection quality"))
     }
 
     async fn set_dsee(&mut self, _dsee: bool) -> Result<(), Error> {
-        unimplemented!()
+        Err(unsupported("DSEE"))
     }
-    async fn get_dsee(&self) -> Result<bool, Error> {
-        unimplemented!()
+    async fn get_dsee(&mut self) -> Result<bool, Error> {
+        Err(unsupported("DSEE"))
     }
 
     async fn set_speak_to_chat(&mut self, _speek_to_chat: bool) -> Result<(), Error> {
-        unimplemented!()
+        Err(unsupported("Speak-to-Chat"))
     }
-    async fn get_speak_to_chat(&self) -> Result<bool, Error> {
-        unimplemented!()
+    async fn get_speak_to_chat(&mut self) -> Result<bool, Error> {
+        Err(unsupported("Speak-to-Chat"))
     }
 
     async fn set_auto_power_off(&mut self, _auto_power_off: bool) -> Result<(), Error> {
-        unimplemented!()
+        Err(unsupported("Auto power off"))
     }
-    async fn get_auto_power_off(&self) -> Result<bool, Error> {
-        unimplemented!()
+    async fn get_auto_power_off(&mut self) -> Result<bool, Error> {
+        Err(unsupported("Auto power off"))
     }
 
     async fn set_pause_on_remove(&mut self, _pause_on_remove: bool) -> Result<(), Error> {
-        unimplemented!()
+        Err(unsupported("Pause on remove"))
+    }
+    async fn get_pause_on_remove(&mut self) -> Result<bool, Error> {
+        Err(unsupported("Pause on remove"))
     }
 
     async fn set_touch_config(&mut self, _touch_sensor: TouchConfig) -> Result<(), Error> {
-        unimplemented!()
+        Err(unsupported("Touch config"))
     }
-    async fn get_touch_config(&self) -> Result<TouchConfig, Error> {
-        unimplemented!()
+    async fn get_touch_config(&mut self) -> Result<TouchConfig, Error> {
+        Err(unsupported("Touch config"))
     }
 
-    async fn set_on_device_anc(&mut self, _on_device_control: bool) -> Result<(), Error> {
-        unimplemented!()
+    async fn set_on_device_anc(&mut self, _on_device_anc: OnDeviceAnc) -> Result<(), Error> {
+        Err(unsupported("On device ANC"))
     }
-    async fn get_on_device_anc(&self) -> Result<bool, Error> {
-        unimplemented!()
+    async fn get_on_device_anc(&mut self) -> Result<OnDeviceAnc, Error> {
+        Err(unsupported("On device ANC"))
     }
 
     async fn set_bt_multipoint(&mut self, _bt_multipoint: bool) -> Result<(), Error> {
-        unimplemented!()
+        Err(unsupported("BT multipoint"))
+    }
+    async fn get_bt_multipoint(&mut self) -> Result<bool, Error> {
+        Err(unsupported("BT multipoint"))
+    }
+
+    /// Changes pushed by the device from now on, including the ones made on
+    /// the device itself. Ends when the connection is closed.
+    fn events(&self) -> BoxStream<'static, DeviceEvent> {
+        stream::empty().boxed()
+    }
+}
+
+/// Something a device may or may not be able to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    DeviceInfo,
+    Battery,
+    RegisteredDevices,
+    Anc,
+    AutoAnc,
+    NcOptimizer,
+    OnDeviceAnc,
+    SixBandEqualizer,
+    TenBandEqualizer,
+    ConnectionQuality,
+    Dsee,
+    SpeakToChat,
+    AutoPowerOff,
+    PauseOnRemove,
+    TouchConfig,
+    BtMultipoint,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 16] = [
+        Feature::DeviceInfo,
+        Feature::Battery,
+        Feature::RegisteredDevices,
+        Feature::Anc,
+        Feature::AutoAnc,
+        Feature::NcOptimizer,
+        Feature::OnDeviceAnc,
+        Feature::SixBandEqualizer,
+        Feature::TenBandEqualizer,
+        Feature::ConnectionQuality,
+        Feature::Dsee,
+        Feature::SpeakToChat,
+        Feature::AutoPowerOff,
+        Feature::PauseOnRemove,
+        Feature::TouchConfig,
+        Feature::BtMultipoint,
+    ];
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Feature::DeviceInfo => "Device info",
+            Feature::Battery => "Battery",
+            Feature::RegisteredDevices => "Registered devices",
+            Feature::Anc => "ANC",
+            Feature::AutoAnc => "Auto ANC",
+            Feature::NcOptimizer => "NC optimizer",
+            Feature::OnDeviceAnc => "On device ANC",
+            Feature::SixBandEqualizer => "6 band equalizer",
+            Feature::TenBandEqualizer => "10 band equalizer",
+            Feature::ConnectionQuality => "Connection quality",
+            Feature::Dsee => "DSEE",
+            Feature::SpeakToChat => "Speak-to-Chat",
+            Feature::AutoPowerOff => "Auto power off",
+            Feature::PauseOnRemove => "Pause on remove",
+            Feature::TouchConfig => "Touch config",
+            Feature::BtMultipoint => "BT multipoint",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Features supported by a device, see [`SonyDevice::capabilities`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Capabilities {
+    pub features: Vec<Feature>,
+}
+
+impl Capabilities {
+    pub fn contains(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+/// Snapshot of everything that can be read from a device.
+///
+/// Settings the device does not support, or that failed to be read, are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceStatus {
+    pub device_info: Option<DeviceInfo>,
+    pub battery: Option<BatteryInfo>,
+    pub anc: Option<Anc>,
+    pub auto_anc: Option<bool>,
+    pub on_device_anc: Option<OnDeviceAnc>,
+    pub equalizer: Option<Equalizer>,
+    pub dsee: Option<bool>,
+    pub speak_to_chat: Option<bool>,
+    pub connection_quality: Option<ConnectionQuality>,
+    pub bt_multipoint: Option<bool>,
+    pub touch_config: Option<TouchConfig>,
+    pub auto_power_off: Option<bool>,
+    pub pause_on_remove: Option<bool>,
+}
+
+impl DeviceStatus {
+    /// Queries every setting supported by `device` in turn.
+    pub async fn query<D: SonyDevice + ?Sized>(device: &mut D) -> Self {
+        let capabilities = device
+            .capabilities()
+            .await
+            .unwrap_or(Capabilities { features: vec![] });
+        let supports = |feature| capabilities.contains(feature);
+        let equalizer = supports(Feature::SixBandEqualizer) || supports(Feature::TenBandEqualizer);
+
+        Self {
+            device_info: read_if(supports(Feature::DeviceInfo), device.get_device_info()).await,
+            battery: read_if(supports(Feature::Battery), device.get_battery_info()).await,
+            anc: read_if(supports(Feature::Anc), device.get_anc()).await,
+            auto_anc: read_if(supports(Feature::AutoAnc), device.get_auto_anc()).await,
+            on_device_anc: read_if(supports(Feature::OnDeviceAnc), device.get_on_device_anc())
+                .await,
+            equalizer: read_if(equalizer, device.get_equalizer()).await,
+            dsee: read_if(supports(Feature::Dsee), device.get_dsee()).await,
+            speak_to_chat: read_if(supports(Feature::SpeakToChat), device.get_speak_to_chat())
+                .await,
+            connection_quality: read_if(
+                supports(Feature::ConnectionQuality),
+                device.get_connection_quality(),
+            )
+            .await,
+            bt_multipoint: read_if(supports(Feature::BtMultipoint), device.get_bt_multipoint())
+                .await,
+            touch_config: read_if(supports(Feature::TouchConfig), device.get_touch_config()).await,
+            auto_power_off: read_if(supports(Feature::AutoPowerOff), device.get_auto_power_off())
+                .await,
+            pause_on_remove: read_if(
+                supports(Feature::PauseOnRemove),
+                device.get_pause_on_remove(),
+            )
+            .await,
+        }
+    }
+}
+
+/// Awaits `read` only when the setting is supported, the request is never
+/// sent otherwise.
+async fn read_if<T>(supported: bool, read: impl Future<Output = Result<T, Error>>) -> Option<T> {
+    if supported {
+        read.await.ok()
+    } else {
+        None
     }
-    async fn get_bt_multipoint(&self) -> Result<bool, Error> {
-        unimplemented!()
+}
+
+/// Change notified by the device without being asked for it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum DeviceEvent {
+    AncChanged(Anc),
+    AutoAncChanged(bool),
+    EqualizerChanged(Equalizer),
+    HeadphonesBatteryChanged(Battery),
+    EarbudsBatteryChanged {
+        left: Battery,
+        right: Battery,
+    },
+    CaseBatteryChanged(Battery),
+    TouchConfigChanged(TouchConfig),
+    ConnectionQualityChanged(ConnectionQuality),
+    DseeChanged(bool),
+    SpeakToChatChanged(bool),
+    AutoPowerOffChanged(bool),
+    PauseOnRemoveChanged(bool),
+    BtMultipointChanged(bool),
+    // Whether the left and right earbuds are worn
+    WearStateChanged {
+        left: bool,
+        right: bool,
+    },
+    // Notification this tool does not decode yet
+    #[serde(serialize_with = "serialize_payload")]
+    Unknown(SonyCommand),
+}
+
+fn serialize_payload<S: Serializer>(
+    command: &SonyCommand,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(&command.payload))
+}
+
+impl Display for DeviceEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceEvent::AncChanged(anc) => write!(f, "ANC: {:?}", anc),
+            DeviceEvent::AutoAncChanged(auto_anc) => {
+                write!(f, "Auto ANC: {}", on_off(*auto_anc))
+            }
+            DeviceEvent::EqualizerChanged(eq) => {
+                write!(f, "Equalizer: {:?} {:?}", eq.profile, eq.bands)
+            }
+            DeviceEvent::HeadphonesBatteryChanged(battery) => write!(f, "Battery: {}", battery),
+            DeviceEvent::EarbudsBatteryChanged { left, right } => {
+                write!(f, "Battery: left {}, right {}", left, right)
+            }
+            DeviceEvent::CaseBatteryChanged(case) => write!(f, "Battery: case {}", case),
+            DeviceEvent::TouchConfigChanged(touch) => {
+                write!(f, "Touch: left {:?}, right {:?}", touch.left, touch.right)
+            }
+            DeviceEvent::ConnectionQualityChanged(connection_quality) => {
+                write!(f, "Connection quality: {:?}", connection_quality)
+            }
+            DeviceEvent::DseeChanged(dsee) => write!(f, "DSEE: {}", on_off(*dsee)),
+            DeviceEvent::SpeakToChatChanged(speak_to_chat) => {
+                write!(f, "Speak-to-Chat: {}", on_off(*speak_to_chat))
+            }
+            DeviceEvent::AutoPowerOffChanged(auto_power_off) => {
+                write!(f, "Auto power off: {}", on_off(*auto_power_off))
+            }
+            DeviceEvent::PauseOnRemoveChanged(pause_on_remove) => {
+                write!(f, "Pause on remove: {}", on_off(*pause_on_remove))
+            }
+            DeviceEvent::BtMultipointChanged(bt_multipoint) => {
+                write!(f, "BT multipoint: {}", on_off(*bt_multipoint))
+            }
+            DeviceEvent::WearStateChanged { left, right } => {
+                let worn = |worn: &bool| if *worn { "worn" } else { "removed" };
+                write!(f, "Wearing: left {}, right {}", worn(left), worn(right))
+            }
+            DeviceEvent::Unknown(command) => {
+                write!(f, "Unknown: {}", hex::encode(&command.payload))
+            }
+        }
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "on"
+    } else {
+        "off"
     }
 }
 
-// TODO: Implement
-pub struct DeviceInfo {}
+/// Protocol revision reported by the device when connecting.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ProtocolInfo {
+    pub version: u32,
+}
+
+impl ProtocolInfo {
+    /// First version speaking the `DataMdr2` table, used by the XM5 models.
+    pub const MDR2: u32 = 0x0200_0000;
+
+    pub fn has_mdr2(&self) -> bool {
+        self.version >= Self::MDR2
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceInfo {
+    pub model_name: String,
+    pub firmware_version: String,
+    pub series: ModelSeries,
+    pub color: ModelColor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelSeries {
+    NoSeries,
+    ExtraBass,
+    UltPowerSound,
+    Hear,
+    Premium,
+    Sports,
+    Casual,
+    // Series not known by this tool, with its raw id
+    Unknown(u8),
+}
+
+impl From<u8> for ModelSeries {
+    fn from(series: u8) -> Self {
+        match series {
+            0x00 => ModelSeries::NoSeries,
+            0x10 => ModelSeries::ExtraBass,
+            0x20 => ModelSeries::UltPowerSound,
+            0x30 => ModelSeries::Hear,
+            0x40 => ModelSeries::Premium,
+            0x50 => ModelSeries::Sports,
+            0x60 => ModelSeries::Casual,
+            series => ModelSeries::Unknown(series),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelColor {
+    Default,
+    Black,
+    White,
+    Silver,
+    Red,
+    Blue,
+    Pink,
+    Yellow,
+    Green,
+    Gray,
+    Gold,
+    Cream,
+    Orange,
+    Brown,
+    Violet,
+    // Colour not known by this tool, with its raw id
+    Unknown(u8),
+}
+
+impl From<u8> for ModelColor {
+    fn from(color: u8) -> Self {
+        match color {
+            0x00 => ModelColor::Default,
+            0x01 => ModelColor::Black,
+            0x02 => ModelColor::White,
+            0x03 => ModelColor::Silver,
+            0x04 => ModelColor::Red,
+            0x05 => ModelColor::Blue,
+            0x06 => ModelColor::Pink,
+            0x07 => ModelColor::Yellow,
+            0x08 => ModelColor::Green,
+            0x09 => ModelColor::Gray,
+            0x0a => ModelColor::Gold,
+            0x0b => ModelColor::Cream,
+            0x0c => ModelColor::Orange,
+            0x0d => ModelColor::Brown,
+            0x0e => ModelColor::Violet,
+            color => ModelColor::Unknown(color),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Battery {
+    // Percentage
+    pub level: u8,
+    pub charging: bool,
+}
+
+impl Display for Battery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", self.level)?;
+        if self.charging {
+            write!(f, " (charging)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum BatteryInfo {
-    Headphones(u8),
-    // Left, Right, case
-    Earbuds(u8, u8, u8),
+    Headphones(Battery),
+    Earbuds {
+        left: Battery,
+        right: Battery,
+        case: Battery,
+    },
 }
 
-pub struct RegisteredDevices {}
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisteredDevice {
+    pub mac: Mac,
+    pub name: String,
+    pub connected: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisteredDevices {
+    pub devices: Vec<RegisteredDevice>,
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Anc {
     AmbientSound { level: u8, voice: bool },
     NoiseCanceling { wind: bool },
     Off,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Modes the ambient sound control gesture on the device cycles through.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AncCycle {
+    NcAmbientOff,
+    NcAmbient,
+    NcOff,
+    AmbientOff,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OnDeviceAnc {
+    pub cycle: AncCycle,
+    // Whether ambient sound reached from the device uses Focus on Voice
+    pub focus_on_voice: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum EqualizerProfile {
     Off,
     Custom1,
     Custom2,
+    // Preset not known by this tool, with its raw id
+    Unknown(u8),
 }
 
-#[derive(Debug, Clone, Copy)]
+impl From<u8> for EqualizerProfile {
+    fn from(preset: u8) -> Self {
+        match preset {
+            0x00 => EqualizerProfile::Off,
+            0xa1 => EqualizerProfile::Custom1,
+            0xa2 => EqualizerProfile::Custom2,
+            preset => EqualizerProfile::Unknown(preset),
+        }
+    }
+}
+
+impl From<EqualizerProfile> for u8 {
+    fn from(profile: EqualizerProfile) -> Self {
+        match profile {
+            EqualizerProfile::Off => 0x00,
+            EqualizerProfile::Custom1 => 0xa1,
+            EqualizerProfile::Custom2 => 0xa2,
+            EqualizerProfile::Unknown(preset) => preset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Bands {
     Zero(),
     FiveBandsAndBass {
@@ -209,6 +875,11 @@ pub enum Bands {
         b6k3: i8,
         b16k: i8,
     },
+    // 31, 63, 125, 250, 500, 1k, 2k, 4k, 8k and 16k Hz
+    TenBandsAndClearBass {
+        clear_bass: i8,
+        bands: [i8; 10],
+    },
 }
 
 impl Bands {
@@ -223,23 +894,56 @@ impl Bands {
                 b6k3,
                 b16k,
             } => {
-                if *bass <= -10 || *bass >= 10 {
-                    return Err(Error::new(format!("Invalid bass value: {:?}", bass)));
+                if *bass < -10 || *bass > 10 {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid bass value: {:?}",
+                        bass
+                    )));
                 }
-                if *b400k <= -10 || *b400k >= 10 {
-                    return Err(Error::new(format!("Invalid 400k value: {:?}", b400k)));
+                if *b400k < -10 || *b400k > 10 {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid 400k value: {:?}",
+                        b400k
+                    )));
                 }
-                if *b1k <= -10 || *b1k >= 10 {
-                    return Err(Error::new(format!("Invalid 1k value: {:?}", b1k)));
+                if *b1k < -10 || *b1k > 10 {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid 1k value: {:?}",
+                        b1k
+                    )));
                 }
-                if *b2k5 <= -10 || *b2k5 >= 10 {
-                    return Err(Error::new(format!("Invalid 2k5 value: {:?}", b2k5)));
+                if *b2k5 < -10 || *b2k5 > 10 {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid 2k5 value: {:?}",
+                        b2k5
+                    )));
                 }
-                if *b6k3 <= -10 || *b6k3 >= 10 {
-                    return Err(Error::new(format!("Invalid 6k3 value: {:?}", b6k3)));
+                if *b6k3 < -10 || *b6k3 > 10 {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid 6k3 value: {:?}",
+                        b6k3
+                    )));
                 }
-                if *b16k <= -10 || *b16k >= 10 {
-                    return Err(Error::new(format!("Invalid 16k value: {:?}", b16k)));
+                if *b16k < -10 || *b16k > 10 {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid 16k value: {:?}",
+                        b16k
+                    )));
+                }
+                Ok(())
+            }
+            Bands::TenBandsAndClearBass { clear_bass, bands } => {
+                if *clear_bass < -10 || *clear_bass > 10 {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid clear bass value: {:?}",
+                        clear_bass
+                    )));
+                }
+                if let Some(band) = bands.iter().find(|b| **b < -10 || **b > 10) {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid band value: {:?}",
+                        band
+                    )));
                 }
                 Ok(())
             }
@@ -247,15 +951,133 @@ impl Bands {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub struct Equalizer {
     pub profile: EqualizerProfile,
     pub bands: Bands,
 }
 
-// TODO: Implement
-pub struct TouchConfig {}
+/// Function triggered by tapping a touch sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TouchFunction {
+    AmbientSoundControl,
+    PlaybackControl,
+    VolumeControl,
+    QuickAccess,
+    NotAssigned,
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TouchConfig {
+    pub left: TouchFunction,
+    pub right: TouchFunction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ConnectionQuality {
+    // Priority on a stable connection
     Stable,
+    // Priority on sound quality
     Quality,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct DseeOnly;
+
+    #[async_trait]
+    impl SonyDevice for DseeOnly {
+        async fn new(_mac: Address) -> Result<Self, Error> {
+            Ok(Self)
+        }
+
+        async fn supports(&mut self, feature: Feature) -> Result<bool, Error> {
+            Ok(feature == Feature::Dsee)
+        }
+
+        async fn get_dsee(&mut self) -> Result<bool, Error> {
+            Ok(true)
+        }
+
+        async fn get_anc(&mut self) -> Result<Anc, Error> {
+            Ok(Anc::Off)
+        }
+    }
+
+    #[tokio::test]
+    async fn capabilities_list_supported_features() {
+        let capabilities = DseeOnly.capabilities().await.unwrap();
+
+        assert_eq!(capabilities.features, vec![Feature::Dsee]);
+        assert!(!capabilities.contains(Feature::Anc));
+    }
+
+    #[test]
+    fn finds_models_by_name() {
+        assert_eq!(find_model("WF-1000XM4").unwrap().name, "WF-1000XM4");
+        assert!(find_model("WH-CH510").is_none());
+    }
+
+    #[tokio::test]
+    async fn identifies_model_from_handshake() {
+        let (client, server) = tokio::io::duplex(1024);
+        let headset = tokio::spawn(wf1000xm4::tests::headset(
+            server,
+            vec![
+                Some(vec![0x01, 0x00, 0x01, 0x00, 0x00, 0x00]),
+                Some([&[0x05, 0x01, 0x0a][..], b"WH-1000XM4"].concat().to_vec()),
+                Some([&[0x05, 0x02, 0x05][..], b"2.0.1"].concat().to_vec()),
+                Some(vec![0x05, 0x03, 0x40, 0x01]),
+            ],
+        ));
+
+        assert_eq!(identify(client).await.unwrap().unwrap().name, "WH-1000XM4");
+        assert_eq!(headset.await.unwrap().len(), 4);
+    }
+
+    #[test]
+    fn validates_band_bounds() {
+        let six = |bass| Bands::FiveBandsAndBass {
+            bass,
+            b400k: 0,
+            b1k: 0,
+            b2k5: 0,
+            b6k3: 0,
+            b16k: 0,
+        };
+        let ten = |band| Bands::TenBandsAndClearBass {
+            clear_bass: 0,
+            bands: [0, 0, 0, 0, 0, 0, 0, 0, 0, band],
+        };
+
+        for value in [-10, 10] {
+            assert!(six(value).validate().is_ok());
+            assert!(ten(value).validate().is_ok());
+        }
+        for value in [-11, 11] {
+            assert!(matches!(
+                six(value).validate(),
+                Err(Error::InvalidArgument(_))
+            ));
+            assert!(matches!(
+                ten(value).validate(),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn status_reports_unsupported_settings_as_unknown() {
+        let status = DeviceStatus::query(&mut DseeOnly).await;
+
+        assert_eq!(status.dsee, Some(true));
+        assert_eq!(status.anc, None);
+        assert_eq!(status.battery, None);
+        assert_eq!(status.bt_multipoint, None);
+    }
+}
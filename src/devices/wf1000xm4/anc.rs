@@ -1,6 +1,9 @@
 use derive_try_from_primitive::TryFromPrimitive;
 
-use crate::{devices::DeviceCommand, DataType, Error, SonyCommand};
+use crate::{
+    devices::{Anc, AncCycle, DeviceCommand, OnDeviceAnc},
+    DataType, Error, SonyCommand,
+};
 
 use super::CommandTypes;
 
@@ -82,3 +85,153 @@ impl TryInto<SonyCommand> for AncCommand {
         })
     }
 }
+
+impl TryFrom<SonyCommand> for AncCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 8 {
+            return Err(Error::BadFrame(format!("Invalid ANC payload: {:?}", bytes)));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid ANC command {:?}", x)))?,
+            continuous: bytes[2] == 0,
+            anc_enable: bytes[3] != 0,
+            anc_mode: bytes[4]
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid ANC mode {:?}", x)))?,
+            nc_wind: bytes[5]
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid ANC wind {:?}", x)))?,
+            as_voice: bytes[6] != 0,
+            as_level: bytes[7]
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid ANC level {:?}", x)))?,
+        })
+    }
+}
+
+impl From<AncCommand> for Anc {
+    fn from(command: AncCommand) -> Self {
+        if !command.anc_enable {
+            return Anc::Off;
+        }
+        match command.anc_mode {
+            AncMode::NoiseCanceling => Anc::NoiseCanceling {
+                wind: !matches!(command.nc_wind, WindCode::NoWind),
+            },
+            AncMode::AmbientSound => Anc::AmbientSound {
+                level: command.as_level as u8,
+                voice: command.as_voice,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum AncCycleCode {
+    NcAmbientOff = 0x01,
+    NcAmbient = 0x02,
+    NcOff = 0x03,
+    AmbientOff = 0x04,
+}
+
+impl From<AncCycle> for AncCycleCode {
+    fn from(cycle: AncCycle) -> Self {
+        match cycle {
+            AncCycle::NcAmbientOff => AncCycleCode::NcAmbientOff,
+            AncCycle::NcAmbient => AncCycleCode::NcAmbient,
+            AncCycle::NcOff => AncCycleCode::NcOff,
+            AncCycle::AmbientOff => AncCycleCode::AmbientOff,
+        }
+    }
+}
+
+impl From<AncCycleCode> for AncCycle {
+    fn from(code: AncCycleCode) -> Self {
+        match code {
+            AncCycleCode::NcAmbientOff => AncCycle::NcAmbientOff,
+            AncCycleCode::NcAmbient => AncCycle::NcAmbient,
+            AncCycleCode::NcOff => AncCycle::NcOff,
+            AncCycleCode::AmbientOff => AncCycle::AmbientOff,
+        }
+    }
+}
+
+/// Modes reachable from the device itself, as set with the NC/AMB gesture.
+#[derive(Debug, Clone, Copy)]
+pub struct OnDeviceAncCommand {
+    pub command: CommandTypes,
+    pub cycle: AncCycleCode,
+    pub focus_on_voice: bool,
+}
+
+impl From<OnDeviceAnc> for OnDeviceAncCommand {
+    fn from(on_device_anc: OnDeviceAnc) -> Self {
+        Self {
+            command: CommandTypes::OnDeviceAncSet,
+            cycle: on_device_anc.cycle.into(),
+            focus_on_voice: on_device_anc.focus_on_voice,
+        }
+    }
+}
+
+impl From<OnDeviceAncCommand> for OnDeviceAnc {
+    fn from(command: OnDeviceAncCommand) -> Self {
+        Self {
+            cycle: command.cycle.into(),
+            focus_on_voice: command.focus_on_voice,
+        }
+    }
+}
+
+impl DeviceCommand for OnDeviceAncCommand {}
+
+impl TryInto<SonyCommand> for OnDeviceAncCommand {
+    type Error = Error;
+
+    fn try_into(self) -> Result<SonyCommand, Self::Error> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&(self.command as u16).to_be_bytes());
+        bytes.push(self.cycle as u8);
+        bytes.push(self.focus_on_voice as u8);
+
+        Ok(SonyCommand {
+            data_type: DataType::DataMdr,
+            seq_number: 0,
+            payload_size: bytes.len() as u8,
+            payload: bytes,
+            checksum: 0,
+        })
+    }
+}
+
+impl TryFrom<SonyCommand> for OnDeviceAncCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 4 {
+            return Err(Error::BadFrame(format!(
+                "Invalid on device ANC payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid on device ANC command {:?}", x)))?,
+            cycle: bytes[2]
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid ANC cycle {:?}", x)))?,
+            focus_on_voice: bytes[3] != 0,
+        })
+    }
+}
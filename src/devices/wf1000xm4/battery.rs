@@ -0,0 +1,37 @@
+use crate::{devices::Battery, Error, SonyCommand};
+
+use super::CommandTypes;
+
+/// Reply to a battery query, with one level and charging flag per battery.
+#[derive(Debug, Clone)]
+pub struct BatteryCommand {
+    pub command: CommandTypes,
+    pub batteries: Vec<Battery>,
+}
+
+impl TryFrom<SonyCommand> for BatteryCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() < 4 || !bytes.len().is_multiple_of(2) {
+            return Err(Error::BadFrame(format!(
+                "Invalid battery payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid battery command {:?}", x)))?,
+            batteries: bytes[2..]
+                .chunks(2)
+                .map(|b| Battery {
+                    level: b[0],
+                    charging: b[1] != 0,
+                })
+                .collect(),
+        })
+    }
+}
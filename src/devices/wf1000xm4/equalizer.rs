@@ -1,5 +1,5 @@
 use crate::{
-    devices::{DeviceCommand, Equalizer, EqualizerProfile},
+    devices::{Bands, DeviceCommand, Equalizer},
     DataType, Error, SonyCommand,
 };
 
@@ -17,6 +17,7 @@ impl TryFrom<Equalizer> for EqualizerCommand {
     type Error = Error;
 
     fn try_from(equalizer: Equalizer) -> Result<Self, Self::Error> {
+        equalizer.bands.validate()?;
         let mut bands = vec![];
 
         match equalizer.bands {
@@ -38,15 +39,16 @@ impl TryFrom<Equalizer> for EqualizerCommand {
                 bands.push((b6k3 + 10) as u8);
                 bands.push((b16k + 10) as u8);
             }
+            crate::devices::Bands::TenBandsAndClearBass { .. } => {
+                return Err(Error::Unsupported(
+                    "10 band equalizer needs the DataMdr2 protocol".to_string(),
+                ));
+            }
         }
 
         Ok(Self {
             command: CommandTypes::EqSet,
-            preset: match equalizer.profile {
-                EqualizerProfile::Off => 0x00,
-                EqualizerProfile::Custom1 => 0xa1,
-                EqualizerProfile::Custom2 => 0xa2,
-            },
+            preset: equalizer.profile.into(),
             nb_bands: bands.len() as u8,
             bands,
         })
@@ -62,10 +64,9 @@ impl TryInto<SonyCommand> for EqualizerCommand {
         let mut bytes = vec![];
 
         bytes.extend_from_slice(&(self.command as u16).to_be_bytes());
-        bytes.push(self.preset as u8);
+        bytes.push(self.preset);
         bytes.push(self.nb_bands);
         bytes.extend_from_slice(&self.bands);
-        println!("EqualizerCommand: {:?}", bytes);
 
         Ok(SonyCommand {
             data_type: DataType::DataMdr,
@@ -76,3 +77,71 @@ impl TryInto<SonyCommand> for EqualizerCommand {
         })
     }
 }
+
+impl TryFrom<SonyCommand> for EqualizerCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() < 4 || bytes.len() != 4 + bytes[3] as usize {
+            return Err(Error::BadFrame(format!(
+                "Invalid equalizer payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid equalizer command {:?}", x)))?,
+            preset: bytes[2],
+            nb_bands: bytes[3],
+            bands: bytes[4..].to_vec(),
+        })
+    }
+}
+
+/// Reverts the +10 offset bands are sent with, see `TryFrom<Equalizer>`.
+pub(crate) fn decode_band(byte: u8) -> Result<i8, Error> {
+    match byte {
+        0..=20 => Ok(byte as i8 - 10),
+        _ => Err(Error::BadFrame(format!(
+            "Invalid equalizer band value: {:?}",
+            byte
+        ))),
+    }
+}
+
+impl TryFrom<EqualizerCommand> for Equalizer {
+    type Error = Error;
+
+    fn try_from(command: EqualizerCommand) -> Result<Self, Self::Error> {
+        let bands = command
+            .bands
+            .iter()
+            .map(|b| decode_band(*b))
+            .collect::<Result<Vec<i8>, Error>>()?;
+        let band = |i: usize| bands[i];
+
+        Ok(Self {
+            profile: command.preset.into(),
+            bands: match command.nb_bands {
+                0 => Bands::Zero(),
+                6 => Bands::FiveBandsAndBass {
+                    bass: band(0),
+                    b400k: band(1),
+                    b1k: band(2),
+                    b2k5: band(3),
+                    b6k3: band(4),
+                    b16k: band(5),
+                },
+                nb_bands => {
+                    return Err(Error::BadFrame(format!(
+                        "Unsupported number of equalizer bands: {:?}",
+                        nb_bands
+                    )))
+                }
+            },
+        })
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{
+    devices::{DeviceEvent, Equalizer},
+    Error, SonyCommand,
+};
+
+use super::{
+    anc::AncCommand, battery::BatteryCommand, equalizer::EqualizerCommand, touch::TouchCommand,
+    ApoEnable, AutoPowerOffCommand, CommandTypes, ConnectionQualityCommand, DseeCommand,
+    MultipointCommand, PauseRemovedCommand, StcCommand,
+};
+
+/// Notification sent when an earbud is put in or taken out of an ear.
+#[derive(Debug, Clone, Copy)]
+pub struct WearStateCommand {
+    pub command: CommandTypes,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl TryFrom<SonyCommand> for WearStateCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 4 {
+            return Err(Error::BadFrame(format!(
+                "Invalid wear state payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid wear state command {:?}", x)))?,
+            left: bytes[2] != 0,
+            right: bytes[3] != 0,
+        })
+    }
+}
+
+fn try_decode(command: SonyCommand) -> Result<Option<DeviceEvent>, Error> {
+    let code = match command.payload.as_slice() {
+        [a, b, ..] => u16::from_be_bytes([*a, *b]),
+        _ => return Ok(None),
+    };
+    let event = match CommandTypes::try_from(code) {
+        Ok(CommandTypes::AncAck) => {
+            let anc: AncCommand = command.try_into()?;
+            DeviceEvent::AncChanged(anc.into())
+        }
+        Ok(CommandTypes::EqAck) => {
+            let eq: EqualizerCommand = command.try_into()?;
+            DeviceEvent::EqualizerChanged(Equalizer::try_from(eq)?)
+        }
+        Ok(CommandTypes::HeadphonesBatteryAck) => {
+            let battery: BatteryCommand = command.try_into()?;
+            match battery.batteries.as_slice() {
+                [battery] => DeviceEvent::HeadphonesBatteryChanged(*battery),
+                _ => return Ok(None),
+            }
+        }
+        Ok(CommandTypes::BatteryAck) => {
+            let battery: BatteryCommand = command.try_into()?;
+            match battery.batteries.as_slice() {
+                [left, right] => DeviceEvent::EarbudsBatteryChanged {
+                    left: *left,
+                    right: *right,
+                },
+                _ => return Ok(None),
+            }
+        }
+        Ok(CommandTypes::CaseBatteryAck) => {
+            let battery: BatteryCommand = command.try_into()?;
+            match battery.batteries.as_slice() {
+                [case] => DeviceEvent::CaseBatteryChanged(*case),
+                _ => return Ok(None),
+            }
+        }
+        Ok(CommandTypes::TouchAck) => {
+            let touch: TouchCommand = command.try_into()?;
+            DeviceEvent::TouchConfigChanged(touch.into())
+        }
+        Ok(CommandTypes::ConnectionQualityAck) => {
+            let connection_quality: ConnectionQualityCommand = command.try_into()?;
+            DeviceEvent::ConnectionQualityChanged(connection_quality.mode.into())
+        }
+        Ok(CommandTypes::DseeAck) => {
+            let dsee: DseeCommand = command.try_into()?;
+            DeviceEvent::DseeChanged(dsee.enable)
+        }
+        Ok(CommandTypes::StcAck) => {
+            let stc: StcCommand = command.try_into()?;
+            DeviceEvent::SpeakToChatChanged(stc.enable)
+        }
+        Ok(CommandTypes::AutoPowerOffAck) => {
+            let apo: AutoPowerOffCommand = command.try_into()?;
+            DeviceEvent::AutoPowerOffChanged(matches!(apo.enable, ApoEnable::On))
+        }
+        Ok(CommandTypes::PauseRemovedAck) => {
+            let pause: PauseRemovedCommand = command.try_into()?;
+            DeviceEvent::PauseOnRemoveChanged(pause.enable)
+        }
+        Ok(CommandTypes::MultipointAck) => {
+            let multipoint: MultipointCommand = command.try_into()?;
+            DeviceEvent::BtMultipointChanged(multipoint.enable)
+        }
+        Ok(CommandTypes::WearStateAck) => {
+            let wear: WearStateCommand = command.try_into()?;
+            DeviceEvent::WearStateChanged {
+                left: wear.left,
+                right: wear.right,
+            }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+/// Turns a notification into an event, keeping the raw frame for the ones
+/// that are unknown or fail to parse.
+pub fn decode_event(command: SonyCommand) -> DeviceEvent {
+    match try_decode(command.clone()) {
+        Ok(Some(event)) => event,
+        _ => DeviceEvent::Unknown(command),
+    }
+}
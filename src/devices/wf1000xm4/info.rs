@@ -0,0 +1,91 @@
+use crate::{
+    devices::{ModelColor, ModelSeries},
+    Error, SonyCommand,
+};
+
+use super::CommandTypes;
+
+/// Handshake reply, the version is followed by feature flags this tool ignores.
+#[derive(Debug, Clone, Copy)]
+pub struct ProtocolInfoCommand {
+    pub command: CommandTypes,
+    pub version: u32,
+}
+
+impl TryFrom<SonyCommand> for ProtocolInfoCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() < 6 {
+            return Err(Error::BadFrame(format!(
+                "Invalid protocol info payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid protocol info command {:?}", x)))?,
+            version: u32::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+        })
+    }
+}
+
+/// Reply carrying a length prefixed string, like the model name or firmware version.
+#[derive(Debug, Clone)]
+pub struct TextInfoCommand {
+    pub command: CommandTypes,
+    pub text: String,
+}
+
+impl TryFrom<SonyCommand> for TextInfoCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() < 3 || bytes.len() != 3 + bytes[2] as usize {
+            return Err(Error::BadFrame(format!(
+                "Invalid info payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid info command {:?}", x)))?,
+            text: String::from_utf8_lossy(&bytes[3..]).into_owned(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SeriesColorCommand {
+    pub command: CommandTypes,
+    pub series: ModelSeries,
+    pub color: ModelColor,
+}
+
+impl TryFrom<SonyCommand> for SeriesColorCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 4 {
+            return Err(Error::BadFrame(format!(
+                "Invalid series payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid series command {:?}", x)))?,
+            series: bytes[2].into(),
+            color: bytes[3].into(),
+        })
+    }
+}
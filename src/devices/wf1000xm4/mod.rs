@@ -1,43 +1,211 @@
-use std::time::Duration;
+use std::{fmt::Display, time::Duration};
 
-use bluer::{
-    rfcomm::{SocketAddr, Stream},
-    Address,
-};
+use async_trait::async_trait;
+use bluer::Address;
 use derive_try_from_primitive::TryFromPrimitive;
+use futures::{
+    future::FutureExt,
+    stream::{BoxStream, StreamExt},
+};
 
-use crate::{DataType, Error, SonyCommand};
+use crate::{
+    transport::{Connection, Transport},
+    DataType, Error, SonyCommand,
+};
 
 use self::{
-    anc::{AncCommand, AncMode, AsLevel, WindCode},
+    anc::{AncCommand, AncMode, AsLevel, OnDeviceAncCommand, WindCode},
+    battery::BatteryCommand,
     equalizer::EqualizerCommand,
+    events::decode_event,
+    info::{ProtocolInfoCommand, SeriesColorCommand, TextInfoCommand},
+    registered::RegisteredDevicesCommand,
+    touch::TouchCommand,
 };
 
-use super::{Anc, DeviceCommand, Equalizer, SonyDevice};
+use super::{
+    connect_service, Anc, BatteryInfo, ConnectionQuality, DeviceCommand, DeviceEvent, DeviceFuture,
+    DeviceInfo, DeviceModel, Equalizer, Feature, OnDeviceAnc, ProtocolInfo, RegisteredDevices,
+    SonyDevice, TouchConfig,
+};
 
 pub mod anc;
+pub mod battery;
 pub mod equalizer;
+pub mod events;
+pub mod info;
+pub mod registered;
+pub mod touch;
+
+pub const MODEL: DeviceModel = DeviceModel {
+    name: "WF-1000XM4",
+    connect,
+    with_transport,
+};
+
+fn connect(mac: Address) -> DeviceFuture {
+    async move { Ok(Box::new(Wf1000xm4::new(mac).await?) as Box<dyn SonyDevice>) }.boxed()
+}
+
+fn with_transport(transport: Box<dyn Transport>) -> Box<dyn SonyDevice> {
+    Box::new(Wf1000xm4::with_transport(transport))
+}
 
 #[derive(Debug)]
 pub struct Wf1000xm4 {
-    stream: Stream,
-    _mac: Address,
+    pub(crate) conn: Connection,
+}
+
+impl Wf1000xm4 {
+    /// Drive the earbuds over an already established transport.
+    pub fn with_transport(transport: impl Transport + 'static) -> Self {
+        Self {
+            conn: Connection::new(transport),
+        }
+    }
+}
+
+impl Wf1000xm4 {
+    /// Sends a Get command and returns the first reply carrying one of `replies`.
+    pub(crate) async fn query(
+        &mut self,
+        command: CommandTypes,
+        replies: &[CommandTypes],
+    ) -> Result<SonyCommand, Error> {
+        let replies: Vec<u16> = replies.iter().map(|r| *r as u16).collect();
+        self.conn.request(GetCommand { command }, &replies).await
+    }
+
+    /// Battery of the over-ear models, which only have one.
+    pub(crate) async fn get_headphones_battery(&mut self) -> Result<BatteryInfo, Error> {
+        let reply = self
+            .query(
+                CommandTypes::HeadphonesBatteryGet,
+                &[
+                    CommandTypes::HeadphonesBatteryRet,
+                    CommandTypes::HeadphonesBatteryAck,
+                ],
+            )
+            .await?;
+        let command: BatteryCommand = reply.try_into()?;
+
+        match command.batteries.as_slice() {
+            [battery] => Ok(BatteryInfo::Headphones(*battery)),
+            _ => Err(Error::BadFrame(format!(
+                "Invalid battery level: {:?}",
+                command
+            ))),
+        }
+    }
 }
 
+#[async_trait]
 impl SonyDevice for Wf1000xm4 {
     async fn new(mac: Address) -> Result<Self, Error> {
-        let target_sa = SocketAddr::new(mac, 9);
-
         // println!("Connecting to [{}]... ", mac);
-        let stream = Stream::connect(target_sa).await.map_err(|x| match x {
-            _ => Error::new(x.to_string()),
-        })?;
+        let stream = connect_service(mac).await?;
         // wait for the connection to be established
         tokio::time::sleep(Duration::from_millis(500)).await;
         // println!("Connected!");
-        Ok(Self {
-            stream: stream,
-            _mac: mac,
+        Ok(Self::with_transport(stream))
+    }
+
+    async fn supports(&mut self, feature: Feature) -> Result<bool, Error> {
+        match feature {
+            Feature::AutoAnc | Feature::NcOptimizer | Feature::TenBandEqualizer => Ok(false),
+            // Only there on recent firmwares, which answer the query
+            Feature::BtMultipoint => match self.get_bt_multipoint().await {
+                Ok(_) => Ok(true),
+                Err(Error::Unsupported(_)) => Ok(false),
+                Err(error) => Err(error),
+            },
+            _ => Ok(true),
+        }
+    }
+
+    async fn get_protocol_info(&mut self) -> Result<ProtocolInfo, Error> {
+        let reply = self
+            .query(
+                CommandTypes::ProtocolInfoGet,
+                &[CommandTypes::ProtocolInfoRet],
+            )
+            .await?;
+        let command: ProtocolInfoCommand = reply.try_into()?;
+        Ok(ProtocolInfo {
+            version: command.version,
+        })
+    }
+
+    async fn get_device_info(&mut self) -> Result<DeviceInfo, Error> {
+        let reply = self
+            .query(CommandTypes::ModelNameGet, &[CommandTypes::ModelNameRet])
+            .await?;
+        let model_name: TextInfoCommand = reply.try_into()?;
+        let reply = self
+            .query(
+                CommandTypes::FirmwareVersionGet,
+                &[CommandTypes::FirmwareVersionRet],
+            )
+            .await?;
+        let firmware_version: TextInfoCommand = reply.try_into()?;
+        let reply = self
+            .query(
+                CommandTypes::SeriesColorGet,
+                &[CommandTypes::SeriesColorRet],
+            )
+            .await?;
+        let series_color: SeriesColorCommand = reply.try_into()?;
+
+        Ok(DeviceInfo {
+            model_name: model_name.text,
+            firmware_version: firmware_version.text,
+            series: series_color.series,
+            color: series_color.color,
+        })
+    }
+
+    async fn get_battery_info(&mut self) -> Result<BatteryInfo, Error> {
+        let reply = self
+            .query(
+                CommandTypes::BatteryGet,
+                &[CommandTypes::BatteryRet, CommandTypes::BatteryAck],
+            )
+            .await?;
+        let earbuds: BatteryCommand = reply.try_into()?;
+        let reply = self
+            .query(
+                CommandTypes::CaseBatteryGet,
+                &[CommandTypes::CaseBatteryRet, CommandTypes::CaseBatteryAck],
+            )
+            .await?;
+        let case: BatteryCommand = reply.try_into()?;
+
+        match (earbuds.batteries.as_slice(), case.batteries.as_slice()) {
+            ([left, right], [case]) => Ok(BatteryInfo::Earbuds {
+                left: *left,
+                right: *right,
+                case: *case,
+            }),
+            _ => Err(Error::BadFrame(format!(
+                "Invalid battery levels: {:?} {:?}",
+                earbuds, case
+            ))),
+        }
+    }
+
+    async fn get_registered_devices(&mut self) -> Result<RegisteredDevices, Error> {
+        let reply = self
+            .query(
+                CommandTypes::RegisteredDevicesGet,
+                &[
+                    CommandTypes::RegisteredDevicesRet,
+                    CommandTypes::RegisteredDevicesAck,
+                ],
+            )
+            .await?;
+        let command: RegisteredDevicesCommand = reply.try_into()?;
+        Ok(RegisteredDevices {
+            devices: command.devices,
         })
     }
 
@@ -52,7 +220,7 @@ impl SonyDevice for Wf1000xm4 {
                 as_voice: voice,
                 as_level: level
                     .try_into()
-                    .map_err(|x| Error::new(format!("Invalid ANC level {:?}", x)))?,
+                    .map_err(|x| Error::InvalidArgument(format!("Invalid ANC level {:?}", x)))?,
             },
             Anc::NoiseCanceling { wind } => AncCommand {
                 command: CommandTypes::AncSet,
@@ -78,26 +246,104 @@ impl SonyDevice for Wf1000xm4 {
             },
         };
 
-        Self::send_with_ack(&mut self.stream, command).await?;
+        self.conn.send_with_ack(command).await?;
         Ok(())
     }
 
+    async fn get_anc(&mut self) -> Result<Anc, Error> {
+        let reply = self
+            .query(
+                CommandTypes::AncGet,
+                &[CommandTypes::AncRet, CommandTypes::AncAck],
+            )
+            .await?;
+        let command: AncCommand = reply.try_into()?;
+        Ok(command.into())
+    }
+
+    async fn set_on_device_anc(&mut self, on_device_anc: OnDeviceAnc) -> Result<(), Error> {
+        let command: OnDeviceAncCommand = on_device_anc.into();
+        self.conn.send_with_ack(command).await?;
+        Ok(())
+    }
+
+    async fn get_on_device_anc(&mut self) -> Result<OnDeviceAnc, Error> {
+        let reply = self
+            .query(
+                CommandTypes::OnDeviceAncGet,
+                &[CommandTypes::OnDeviceAncRet, CommandTypes::OnDeviceAncAck],
+            )
+            .await?;
+        let command: OnDeviceAncCommand = reply.try_into()?;
+        Ok(command.into())
+    }
+
     async fn set_equalizer(&mut self, eq: Equalizer) -> Result<(), Error> {
         let command: EqualizerCommand = eq.try_into()?;
-        Self::send_with_ack(&mut self.stream, command).await?;
+        self.conn.send_with_ack(command).await?;
+        Ok(())
+    }
+
+    async fn get_equalizer(&mut self) -> Result<Equalizer, Error> {
+        let reply = self
+            .query(
+                CommandTypes::EqGet,
+                &[CommandTypes::EqRet, CommandTypes::EqAck],
+            )
+            .await?;
+        let command: EqualizerCommand = reply.try_into()?;
+        command.try_into()
+    }
+
+    async fn set_connection_quality(
+        &mut self,
+        connection_quality: ConnectionQuality,
+    ) -> Result<(), Error> {
+        self.conn
+            .send_with_ack(ConnectionQualityCommand {
+                command: CommandTypes::ConnectionQualitySet,
+                mode: connection_quality.into(),
+            })
+            .await?;
+
         Ok(())
     }
 
+    async fn get_connection_quality(&mut self) -> Result<ConnectionQuality, Error> {
+        let reply = self
+            .query(
+                CommandTypes::ConnectionQualityGet,
+                &[
+                    CommandTypes::ConnectionQualityRet,
+                    CommandTypes::ConnectionQualityAck,
+                ],
+            )
+            .await?;
+        let command: ConnectionQualityCommand = reply.try_into()?;
+        Ok(command.mode.into())
+    }
+
     async fn set_dsee(&mut self, dsee: bool) -> Result<(), Error> {
         let command: DseeCommand = DseeCommand {
             command: CommandTypes::DseeSet,
             enable: dsee,
         };
 
-        Self::send_with_ack(&mut self.stream, command).await?;
+        self.conn.send_with_ack(command).await?;
         Ok(())
     }
 
+    async fn get_dsee(&mut self) -> Result<bool, Error> {
+        let reply = self
+            .query(
+                CommandTypes::DseeGet,
+                &[CommandTypes::DseeRet, CommandTypes::DseeAck],
+            )
+            .await?;
+        let command: DseeCommand = reply.try_into()?;
+        Ok(command.enable)
+    }
+
     async fn set_speak_to_chat(&mut self, speak_to_chat: bool) -> Result<(), Error> {
         let command: StcCommand = StcCommand {
             command: CommandTypes::StcSet,
@@ -105,49 +351,224 @@ impl SonyDevice for Wf1000xm4 {
             _unknown: 0x01,
         };
 
-        Self::send_with_ack(&mut self.stream, command).await?;
+        self.conn.send_with_ack(command).await?;
         Ok(())
     }
 
+    async fn get_speak_to_chat(&mut self) -> Result<bool, Error> {
+        let reply = self
+            .query(
+                CommandTypes::StcGet,
+                &[CommandTypes::StcRet, CommandTypes::StcAck],
+            )
+            .await?;
+        let command: StcCommand = reply.try_into()?;
+        Ok(command.enable)
+    }
+
     async fn set_auto_power_off(&mut self, auto_power_off: bool) -> Result<(), Error> {
-        Self::send_with_ack(
-            &mut self.stream,
-            AutoPowerOffCommand {
-                command: CommandTypes::AncSet,
+        self.conn
+            .send_with_ack(AutoPowerOffCommand {
+                command: CommandTypes::AutoPowerOffSet,
                 enable: match auto_power_off {
                     true => ApoEnable::On,
                     false => ApoEnable::Off,
                 },
                 _unknown: 0x00,
-            },
-        )
-        .await?;
+            })
+            .await?;
 
         Ok(())
     }
 
+    async fn get_auto_power_off(&mut self) -> Result<bool, Error> {
+        let reply = self
+            .query(
+                CommandTypes::AutoPowerOffGet,
+                &[CommandTypes::AutoPowerOffRet, CommandTypes::AutoPowerOffAck],
+            )
+            .await?;
+        let command: AutoPowerOffCommand = reply.try_into()?;
+        Ok(matches!(command.enable, ApoEnable::On))
+    }
+
     async fn set_pause_on_remove(&mut self, pause_on_remove: bool) -> Result<(), Error> {
-        Self::send_with_ack(
-            &mut self.stream,
-            PauseRemovedCommand {
-                command: CommandTypes::AncSet,
+        self.conn
+            .send_with_ack(PauseRemovedCommand {
+                command: CommandTypes::PauseRemovedSet,
                 enable: pause_on_remove,
-            },
-        )
-        .await?;
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_pause_on_remove(&mut self) -> Result<bool, Error> {
+        let reply = self
+            .query(
+                CommandTypes::PauseRemovedGet,
+                &[CommandTypes::PauseRemovedRet, CommandTypes::PauseRemovedAck],
+            )
+            .await?;
+        let command: PauseRemovedCommand = reply.try_into()?;
+        Ok(command.enable)
+    }
+
+    async fn set_bt_multipoint(&mut self, bt_multipoint: bool) -> Result<(), Error> {
+        // Firmwares without multipoint ACK the command but never apply it
+        let reply = self
+            .conn
+            .try_request(
+                MultipointCommand {
+                    command: CommandTypes::MultipointSet,
+                    enable: bt_multipoint,
+                },
+                &[CommandTypes::MultipointAck as u16],
+            )
+            .await?
+            .ok_or_else(|| multipoint_unsupported("no reply"))?;
+        let command: MultipointCommand = reply.try_into()?;
+        if command.enable != bt_multipoint {
+            return Err(multipoint_unsupported("setting rejected"));
+        }
+        Ok(())
+    }
+
+    async fn get_bt_multipoint(&mut self) -> Result<bool, Error> {
+        let replies = [
+            CommandTypes::MultipointRet as u16,
+            CommandTypes::MultipointAck as u16,
+        ];
+        let reply = self
+            .conn
+            .try_request(
+                GetCommand {
+                    command: CommandTypes::MultipointGet,
+                },
+                &replies,
+            )
+            .await?
+            .ok_or_else(|| multipoint_unsupported("no reply"))?;
+        let command: MultipointCommand = reply.try_into()?;
+        Ok(command.enable)
+    }
 
+    async fn set_touch_config(&mut self, touch_config: TouchConfig) -> Result<(), Error> {
+        let command: TouchCommand = touch_config.into();
+        self.conn.send_with_ack(command).await?;
         Ok(())
     }
+
+    async fn get_touch_config(&mut self) -> Result<TouchConfig, Error> {
+        let reply = self
+            .query(
+                CommandTypes::TouchGet,
+                &[CommandTypes::TouchRet, CommandTypes::TouchAck],
+            )
+            .await?;
+        let command: TouchCommand = reply.try_into()?;
+        Ok(command.into())
+    }
+
+    fn events(&self) -> BoxStream<'static, DeviceEvent> {
+        self.conn.notifications().map(decode_event).boxed()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
 #[repr(u16)]
 pub enum CommandTypes {
+    // Get is answered with Ret, Set is acknowledged then answered with Ack
+    AncGet = 0x6615,
+    AncRet = 0x6715,
     AncSet = 0x6815,
     AncAck = 0x6915,
+    OnDeviceAncGet = 0x6617,
+    OnDeviceAncRet = 0x6717,
+    OnDeviceAncSet = 0x6817,
+    OnDeviceAncAck = 0x6917,
+    ConnectionQualityGet = 0xe600,
+    ConnectionQualityRet = 0xe700,
+    ConnectionQualitySet = 0xe800,
+    ConnectionQualityAck = 0xe900,
+    DseeGet = 0xe601,
+    DseeRet = 0xe701,
     DseeSet = 0xe801,
+    DseeAck = 0xe901,
+    StcGet = 0xf602,
+    StcRet = 0xf702,
     StcSet = 0xf802,
+    StcAck = 0xf902,
+    PauseRemovedGet = 0xf601,
+    PauseRemovedRet = 0xf701,
+    PauseRemovedSet = 0xf801,
+    PauseRemovedAck = 0xf901,
+    // Only ever notified, payload is the left and right worn flags
+    WearStateAck = 0xf903,
+    MultipointGet = 0xf607,
+    MultipointRet = 0xf707,
+    MultipointSet = 0xf807,
+    MultipointAck = 0xf907,
+    TouchGet = 0xf606,
+    TouchRet = 0xf706,
+    TouchSet = 0xf806,
+    TouchAck = 0xf906,
+    AutoPowerOffGet = 0x2605,
+    AutoPowerOffRet = 0x2705,
+    AutoPowerOffSet = 0x2805,
+    AutoPowerOffAck = 0x2905,
+    // Handshake, answered with the protocol version
+    ProtocolInfoGet = 0x0000,
+    ProtocolInfoRet = 0x0100,
+    ModelNameGet = 0x0401,
+    ModelNameRet = 0x0501,
+    FirmwareVersionGet = 0x0402,
+    FirmwareVersionRet = 0x0502,
+    SeriesColorGet = 0x0403,
+    SeriesColorRet = 0x0503,
+    HeadphonesBatteryGet = 0x1000,
+    HeadphonesBatteryRet = 0x1100,
+    HeadphonesBatteryAck = 0x1300,
+    // Left and right earbuds
+    BatteryGet = 0x1001,
+    BatteryRet = 0x1101,
+    BatteryAck = 0x1301,
+    CaseBatteryGet = 0x1002,
+    CaseBatteryRet = 0x1102,
+    CaseBatteryAck = 0x1302,
+    RegisteredDevicesGet = 0x3602,
+    RegisteredDevicesRet = 0x3702,
+    RegisteredDevicesAck = 0x3902,
+    NcOptimizerSet = 0x8401,
+    NcOptimizerAck = 0x8501,
+    EqGet = 0x5600,
+    EqRet = 0x5700,
     EqSet = 0x5800,
+    EqAck = 0x5900,
+}
+
+/// Query for the current state, answered by the matching Ret command.
+#[derive(Debug, Clone, Copy)]
+struct GetCommand {
+    command: CommandTypes,
+}
+
+impl DeviceCommand for GetCommand {}
+
+impl TryInto<SonyCommand> for GetCommand {
+    type Error = Error;
+
+    fn try_into(self) -> Result<SonyCommand, Self::Error> {
+        let bytes = (self.command as u16).to_be_bytes().to_vec();
+
+        Ok(SonyCommand {
+            data_type: DataType::DataMdr,
+            seq_number: 0,
+            payload_size: bytes.len() as u8,
+            payload: bytes,
+            checksum: 0,
+        })
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -177,6 +598,104 @@ impl TryInto<SonyCommand> for DseeCommand {
     }
 }
 
+impl TryFrom<SonyCommand> for DseeCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 3 {
+            return Err(Error::BadFrame(format!(
+                "Invalid DSEE payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid DSEE command {:?}", x)))?,
+            enable: bytes[2] != 0,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum ConnectionQualityMode {
+    Quality = 0x00,
+    Stable = 0x01,
+}
+
+impl From<ConnectionQuality> for ConnectionQualityMode {
+    fn from(connection_quality: ConnectionQuality) -> Self {
+        match connection_quality {
+            ConnectionQuality::Quality => ConnectionQualityMode::Quality,
+            ConnectionQuality::Stable => ConnectionQualityMode::Stable,
+        }
+    }
+}
+
+impl From<ConnectionQualityMode> for ConnectionQuality {
+    fn from(mode: ConnectionQualityMode) -> Self {
+        match mode {
+            ConnectionQualityMode::Quality => ConnectionQuality::Quality,
+            ConnectionQualityMode::Stable => ConnectionQuality::Stable,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ConnectionQualityCommand {
+    command: CommandTypes,
+    mode: ConnectionQualityMode,
+}
+
+impl DeviceCommand for ConnectionQualityCommand {}
+
+impl TryInto<SonyCommand> for ConnectionQualityCommand {
+    type Error = Error;
+
+    fn try_into(self) -> Result<SonyCommand, Self::Error> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&(self.command as u16).to_be_bytes());
+        bytes.push(self.mode as u8);
+
+        Ok(SonyCommand {
+            data_type: DataType::DataMdr,
+            seq_number: 0,
+            payload_size: bytes.len() as u8,
+            payload: bytes,
+            checksum: 0,
+        })
+    }
+}
+
+impl TryFrom<SonyCommand> for ConnectionQualityCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 3 {
+            return Err(Error::BadFrame(format!(
+                "Invalid connection quality payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| {
+                    Error::BadFrame(format!("Invalid connection quality command {:?}", x))
+                })?,
+            mode: bytes[2]
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid connection quality mode {:?}", x)))?,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 struct StcCommand {
     command: CommandTypes,
@@ -207,6 +726,28 @@ impl TryInto<SonyCommand> for StcCommand {
     }
 }
 
+impl TryFrom<SonyCommand> for StcCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 4 {
+            return Err(Error::BadFrame(format!(
+                "Invalid Speak-to-Chat payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid Speak-to-Chat command {:?}", x)))?,
+            enable: bytes[2] == 0,
+            _unknown: bytes[3],
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, TryFromPrimitive)]
 #[repr(u8)]
 pub enum ApoEnable {
@@ -244,6 +785,30 @@ impl TryInto<SonyCommand> for AutoPowerOffCommand {
     }
 }
 
+impl TryFrom<SonyCommand> for AutoPowerOffCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 4 {
+            return Err(Error::BadFrame(format!(
+                "Invalid auto power off payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid auto power off command {:?}", x)))?,
+            enable: bytes[2]
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid auto power off mode {:?}", x)))?,
+            _unknown: bytes[3],
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct PauseRemovedCommand {
     command: CommandTypes,
@@ -270,3 +835,663 @@ impl TryInto<SonyCommand> for PauseRemovedCommand {
         })
     }
 }
+
+impl TryFrom<SonyCommand> for PauseRemovedCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 3 {
+            return Err(Error::BadFrame(format!(
+                "Invalid pause when removed payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| {
+                    Error::BadFrame(format!("Invalid pause when removed command {:?}", x))
+                })?,
+            enable: bytes[2] == 0,
+        })
+    }
+}
+
+fn multipoint_unsupported(reason: impl Display) -> Error {
+    Error::Unsupported(format!(
+        "BT multipoint is unsupported on this firmware: {}",
+        reason
+    ))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MultipointCommand {
+    command: CommandTypes,
+    enable: bool,
+}
+
+impl DeviceCommand for MultipointCommand {}
+
+impl TryInto<SonyCommand> for MultipointCommand {
+    type Error = Error;
+
+    fn try_into(self) -> Result<SonyCommand, Self::Error> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&(self.command as u16).to_be_bytes());
+        bytes.push(!self.enable as u8);
+
+        Ok(SonyCommand {
+            data_type: DataType::DataMdr,
+            seq_number: 0,
+            payload_size: bytes.len() as u8,
+            payload: bytes,
+            checksum: 0,
+        })
+    }
+}
+
+impl TryFrom<SonyCommand> for MultipointCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 3 {
+            return Err(Error::BadFrame(format!(
+                "Invalid multipoint payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid multipoint command {:?}", x)))?,
+            enable: bytes[2] == 0,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    use super::*;
+    use crate::{
+        decoder::FrameDecoder,
+        devices::{
+            AncCycle, Bands, Battery, EqualizerProfile, ModelColor, ModelSeries, RegisteredDevice,
+            TouchFunction,
+        },
+        Mac,
+    };
+
+    fn frame(data_type: DataType, seq_number: u32, payload: Vec<u8>) -> Vec<u8> {
+        SonyCommand {
+            data_type,
+            seq_number,
+            payload_size: payload.len() as u8,
+            payload,
+            checksum: 0,
+        }
+        .try_into()
+        .unwrap()
+    }
+
+    /// Plays the headset side: ACKs every command, answers it with the
+    /// matching entry of `replies` and returns the payloads received once
+    /// the device is dropped.
+    pub(crate) async fn headset(
+        stream: DuplexStream,
+        replies: Vec<Option<Vec<u8>>>,
+    ) -> Vec<Vec<u8>> {
+        headset_frames(stream, replies)
+            .await
+            .into_iter()
+            .map(|(_, payload)| payload)
+            .collect()
+    }
+
+    /// Like [`headset`], but keeps the data type of the commands received,
+    /// which the replies are sent with.
+    pub(crate) async fn headset_frames(
+        mut stream: DuplexStream,
+        replies: Vec<Option<Vec<u8>>>,
+    ) -> Vec<(DataType, Vec<u8>)> {
+        let mut decoder = FrameDecoder::new();
+        let mut frames = vec![];
+        let mut replies = replies.into_iter();
+        let mut seq_number = 0;
+        let mut buffer = [0; 1024];
+        loop {
+            let len = stream.read(&mut buffer).await.unwrap();
+            if len == 0 {
+                break;
+            }
+            decoder.push(&buffer[..len]);
+            while let Some(command) = decoder.next_command() {
+                let command = command.unwrap();
+                if command.data_type == DataType::Ack {
+                    continue;
+                }
+                let ack = frame(DataType::Ack, 1 - command.seq_number, vec![]);
+                stream.write_all(&ack).await.unwrap();
+                frames.push((command.data_type, command.payload));
+
+                if let Some(Some(reply)) = replies.next() {
+                    let reply = frame(command.data_type, seq_number, reply);
+                    stream.write_all(&reply).await.unwrap();
+                    seq_number = 1 - seq_number;
+                }
+            }
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn sends_commands_over_any_transport() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(server, vec![None, None]));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        device.set_dsee(true).await.unwrap();
+        device
+            .set_anc(Anc::AmbientSound {
+                level: 5,
+                voice: true,
+            })
+            .await
+            .unwrap();
+
+        drop(device);
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![
+                vec![0xe8, 0x01, 0x01],
+                vec![0x68, 0x15, 0x01, 0x01, 0x01, 0x02, 0x01, 0x05]
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_back_anc() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(
+            server,
+            vec![Some(vec![0x67, 0x15, 0x01, 0x01, 0x01, 0x02, 0x01, 0x05])],
+        ));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        assert_eq!(
+            device.get_anc().await.unwrap(),
+            Anc::AmbientSound {
+                level: 5,
+                voice: true
+            }
+        );
+        drop(device);
+        assert_eq!(headset.await.unwrap(), vec![vec![0x66, 0x15]]);
+    }
+
+    #[tokio::test]
+    async fn sets_and_reads_back_touch_config() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(
+            server,
+            vec![None, Some(vec![0xf7, 0x06, 0x02, 0x10, 0xff])],
+        ));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        device
+            .set_touch_config(TouchConfig {
+                left: TouchFunction::AmbientSoundControl,
+                right: TouchFunction::QuickAccess,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            device.get_touch_config().await.unwrap(),
+            TouchConfig {
+                left: TouchFunction::VolumeControl,
+                right: TouchFunction::NotAssigned,
+            }
+        );
+        drop(device);
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![vec![0xf8, 0x06, 0x02, 0x00, 0x35], vec![0xf6, 0x06]]
+        );
+    }
+
+    #[tokio::test]
+    async fn sets_and_reads_back_connection_quality() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(server, vec![None, Some(vec![0xe7, 0x00, 0x01])]));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        device
+            .set_connection_quality(ConnectionQuality::Quality)
+            .await
+            .unwrap();
+        assert_eq!(
+            device.get_connection_quality().await.unwrap(),
+            ConnectionQuality::Stable
+        );
+        drop(device);
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![vec![0xe8, 0x00, 0x00], vec![0xe6, 0x00]]
+        );
+    }
+
+    #[tokio::test]
+    async fn sets_bt_multipoint() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(server, vec![Some(vec![0xf9, 0x07, 0x00])]));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        device.set_bt_multipoint(true).await.unwrap();
+        drop(device);
+        assert_eq!(headset.await.unwrap(), vec![vec![0xf8, 0x07, 0x00]]);
+    }
+
+    #[tokio::test]
+    async fn reports_unsupported_bt_multipoint() {
+        let (client, server) = duplex(1024);
+        // ACKed but never applied
+        let headset = tokio::spawn(headset(server, vec![None]));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        let error = device.set_bt_multipoint(true).await.unwrap_err();
+        assert!(matches!(error, Error::Unsupported(_)));
+        drop(device);
+        headset.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_connection_errors_for_bt_multipoint() {
+        let (client, server) = duplex(1024);
+        drop(server);
+
+        let mut device = Wf1000xm4::with_transport(client);
+        let error = device.get_bt_multipoint().await.unwrap_err();
+        assert!(matches!(error, Error::Io(_)));
+        let error = device.supports(Feature::BtMultipoint).await.unwrap_err();
+        assert!(matches!(error, Error::Io(_)));
+    }
+
+    #[tokio::test]
+    async fn sets_and_reads_back_on_device_anc() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(
+            server,
+            vec![None, Some(vec![0x67, 0x17, 0x03, 0x00])],
+        ));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        device
+            .set_on_device_anc(OnDeviceAnc {
+                cycle: AncCycle::AmbientOff,
+                focus_on_voice: true,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            device.get_on_device_anc().await.unwrap(),
+            OnDeviceAnc {
+                cycle: AncCycle::NcOff,
+                focus_on_voice: false,
+            }
+        );
+        drop(device);
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![vec![0x68, 0x17, 0x04, 0x01], vec![0x66, 0x17]]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_back_toggles() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(
+            server,
+            vec![
+                Some(vec![0xe7, 0x01, 0x01]),
+                Some(vec![0xf7, 0x02, 0x01, 0x01]),
+                Some(vec![0x27, 0x05, 0x11, 0x00]),
+                Some(vec![0xf7, 0x01, 0x00]),
+            ],
+        ));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        assert!(device.get_dsee().await.unwrap());
+        assert!(!device.get_speak_to_chat().await.unwrap());
+        assert!(!device.get_auto_power_off().await.unwrap());
+        assert!(device.get_pause_on_remove().await.unwrap());
+        drop(device);
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![
+                vec![0xe6, 0x01],
+                vec![0xf6, 0x02],
+                vec![0x26, 0x05],
+                vec![0xf6, 0x01]
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_back_equalizer() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(
+            server,
+            vec![Some(vec![
+                0x57, 0x00, 0xa1, 0x06, 0x00, 0x0a, 0x14, 0x0b, 0x09, 0x0a,
+            ])],
+        ));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        assert_eq!(
+            device.get_equalizer().await.unwrap(),
+            Equalizer {
+                profile: EqualizerProfile::Custom1,
+                bands: Bands::FiveBandsAndBass {
+                    bass: -10,
+                    b400k: 0,
+                    b1k: 10,
+                    b2k5: 1,
+                    b6k3: -1,
+                    b16k: 0,
+                },
+            }
+        );
+        drop(device);
+        assert_eq!(headset.await.unwrap(), vec![vec![0x56, 0x00]]);
+    }
+
+    #[tokio::test]
+    async fn reads_back_battery() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(
+            server,
+            vec![
+                Some(vec![0x11, 0x01, 0x50, 0x00, 0x46, 0x01]),
+                Some(vec![0x11, 0x02, 0x1e, 0x00]),
+            ],
+        ));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        assert_eq!(
+            device.get_battery_info().await.unwrap(),
+            BatteryInfo::Earbuds {
+                left: Battery {
+                    level: 80,
+                    charging: false
+                },
+                right: Battery {
+                    level: 70,
+                    charging: true
+                },
+                case: Battery {
+                    level: 30,
+                    charging: false
+                },
+            }
+        );
+        drop(device);
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![vec![0x10, 0x01], vec![0x10, 0x02]]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_back_device_info() {
+        let (client, server) = duplex(1024);
+        let mut model_name = vec![0x05, 0x01, 0x0a];
+        model_name.extend_from_slice(b"WF-1000XM4");
+        let mut firmware_version = vec![0x05, 0x02, 0x05];
+        firmware_version.extend_from_slice(b"1.6.0");
+        let headset = tokio::spawn(headset(
+            server,
+            vec![
+                Some(model_name),
+                Some(firmware_version),
+                Some(vec![0x05, 0x03, 0x40, 0x42]),
+            ],
+        ));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        assert_eq!(
+            device.get_device_info().await.unwrap(),
+            DeviceInfo {
+                model_name: "WF-1000XM4".to_string(),
+                firmware_version: "1.6.0".to_string(),
+                series: ModelSeries::Premium,
+                color: ModelColor::Unknown(0x42),
+            }
+        );
+        drop(device);
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![vec![0x04, 0x01], vec![0x04, 0x02], vec![0x04, 0x03]]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_back_registered_devices() {
+        let (client, server) = duplex(1024);
+        let mut reply = vec![0x37, 0x02, 0x02];
+        reply.extend_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x01, 0x06]);
+        reply.extend_from_slice(b"laptop");
+        reply.extend_from_slice(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff, 0x00, 0x05]);
+        reply.extend_from_slice(b"phone");
+        let headset = tokio::spawn(headset(server, vec![Some(reply)]));
+
+        let mut device = Wf1000xm4::with_transport(client);
+        let registered = device.get_registered_devices().await.unwrap();
+        drop(device);
+
+        assert_eq!(
+            registered.devices,
+            vec![
+                RegisteredDevice {
+                    mac: Mac {
+                        bytes: [0x00, 0x11, 0x22, 0x33, 0x44, 0x55]
+                    },
+                    name: "laptop".to_string(),
+                    connected: true,
+                },
+                RegisteredDevice {
+                    mac: Mac {
+                        bytes: [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]
+                    },
+                    name: "phone".to_string(),
+                    connected: false,
+                },
+            ]
+        );
+        assert_eq!(registered.devices[1].mac.to_string(), "AA:BB:CC:DD:EE:FF");
+        assert_eq!(headset.await.unwrap(), vec![vec![0x36, 0x02]]);
+    }
+
+    #[test]
+    fn reports_unknown_equalizer_preset() {
+        let command: EqualizerCommand = SonyCommand {
+            data_type: DataType::DataMdr,
+            seq_number: 0,
+            payload_size: 4,
+            payload: vec![0x59, 0x00, 0x10, 0x00],
+            checksum: 0,
+        }
+        .try_into()
+        .unwrap();
+        let equalizer: Equalizer = command.clone().try_into().unwrap();
+
+        assert_eq!(equalizer.profile, EqualizerProfile::Unknown(0x10));
+        assert_eq!(equalizer.bands, Bands::Zero());
+
+        // Written back as is
+        let command: EqualizerCommand = equalizer.try_into().unwrap();
+        assert_eq!(command.preset, 0x10);
+    }
+
+    #[test]
+    fn rejects_out_of_range_equalizer_bands() {
+        let command: EqualizerCommand = SonyCommand {
+            data_type: DataType::DataMdr,
+            seq_number: 0,
+            payload_size: 10,
+            payload: vec![0x59, 0x00, 0xa1, 0x06, 0x0a, 0x0a, 0x8a, 0x0a, 0x0a, 0x15],
+            checksum: 0,
+        }
+        .try_into()
+        .unwrap();
+
+        assert!(matches!(
+            Equalizer::try_from(command),
+            Err(Error::BadFrame(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_to_encode_out_of_range_equalizer_bands() {
+        for bass in [11, 117, 118, i8::MAX, i8::MIN] {
+            let equalizer = Equalizer {
+                profile: EqualizerProfile::Custom1,
+                bands: Bands::FiveBandsAndBass {
+                    bass,
+                    b400k: 0,
+                    b1k: 0,
+                    b2k5: 0,
+                    b6k3: 0,
+                    b16k: 0,
+                },
+            };
+            assert!(matches!(
+                EqualizerCommand::try_from(equalizer),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_notifications_as_events() {
+        let (client, mut server) = duplex(1024);
+        let device = Wf1000xm4::with_transport(client);
+        let mut events = device.events();
+
+        let mut bytes = frame(
+            DataType::DataMdr,
+            0,
+            vec![0x69, 0x15, 0x01, 0x01, 0x00, 0x03, 0x00, 0x01],
+        );
+        bytes.extend(frame(DataType::DataMdr, 1, vec![0xf9, 0x03, 0x01, 0x00]));
+        bytes.extend(frame(DataType::DataMdr, 0, vec![0x13, 0x02, 0x32, 0x01]));
+        bytes.extend(frame(DataType::DataMdr, 1, vec![0x12, 0x34]));
+        server.write_all(&bytes).await.unwrap();
+
+        assert_eq!(
+            events.next().await,
+            Some(DeviceEvent::AncChanged(Anc::NoiseCanceling { wind: true }))
+        );
+        assert_eq!(
+            events.next().await,
+            Some(DeviceEvent::WearStateChanged {
+                left: true,
+                right: false
+            })
+        );
+        assert_eq!(
+            events.next().await,
+            Some(DeviceEvent::CaseBatteryChanged(Battery {
+                level: 50,
+                charging: true
+            }))
+        );
+        assert!(matches!(
+            events.next().await,
+            Some(DeviceEvent::Unknown(command)) if command.payload == vec![0x12, 0x34]
+        ));
+    }
+
+    #[test]
+    fn decodes_setting_notifications() {
+        let decode = |payload: Vec<u8>| {
+            decode_event(SonyCommand {
+                data_type: DataType::DataMdr,
+                seq_number: 0,
+                payload_size: payload.len() as u8,
+                payload,
+                checksum: 0,
+            })
+        };
+
+        assert_eq!(
+            decode(vec![0xe9, 0x00, 0x01]),
+            DeviceEvent::ConnectionQualityChanged(ConnectionQuality::Stable)
+        );
+        assert_eq!(
+            decode(vec![0xe9, 0x01, 0x01]),
+            DeviceEvent::DseeChanged(true)
+        );
+        assert_eq!(
+            decode(vec![0xf9, 0x02, 0x01, 0x01]),
+            DeviceEvent::SpeakToChatChanged(false)
+        );
+        assert_eq!(
+            decode(vec![0x29, 0x05, 0x10, 0x00]),
+            DeviceEvent::AutoPowerOffChanged(true)
+        );
+        assert_eq!(
+            decode(vec![0xf9, 0x01, 0x00]),
+            DeviceEvent::PauseOnRemoveChanged(true)
+        );
+        assert_eq!(
+            decode(vec![0xf9, 0x07, 0x01]),
+            DeviceEvent::BtMultipointChanged(false)
+        );
+        assert!(matches!(
+            decode(vec![0x29, 0x05, 0x42, 0x00]),
+            DeviceEvent::Unknown(_)
+        ));
+    }
+
+    #[test]
+    fn decodes_anc_payloads() {
+        let decode = |payload: &[u8]| -> Anc {
+            let command: AncCommand = SonyCommand {
+                data_type: DataType::DataMdr,
+                seq_number: 0,
+                payload_size: payload.len() as u8,
+                payload: payload.to_vec(),
+                checksum: 0,
+            }
+            .try_into()
+            .unwrap();
+            command.into()
+        };
+
+        assert_eq!(
+            decode(&[0x69, 0x15, 0x01, 0x00, 0x00, 0x02, 0x00, 0x01]),
+            Anc::Off
+        );
+        assert_eq!(
+            decode(&[0x69, 0x15, 0x01, 0x01, 0x00, 0x02, 0x00, 0x01]),
+            Anc::NoiseCanceling { wind: false }
+        );
+        assert_eq!(
+            decode(&[0x69, 0x15, 0x01, 0x01, 0x00, 0x03, 0x00, 0x01]),
+            Anc::NoiseCanceling { wind: true }
+        );
+        assert_eq!(
+            decode(&[0x67, 0x15, 0x01, 0x01, 0x01, 0x02, 0x00, 0x14]),
+            Anc::AmbientSound {
+                level: 20,
+                voice: false
+            }
+        );
+    }
+}
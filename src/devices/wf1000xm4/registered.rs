@@ -0,0 +1,53 @@
+use crate::{devices::RegisteredDevice, Error, Mac, SonyCommand};
+
+use super::CommandTypes;
+
+/// Reply listing the hosts the earbuds are paired with.
+#[derive(Debug, Clone)]
+pub struct RegisteredDevicesCommand {
+    pub command: CommandTypes,
+    pub devices: Vec<RegisteredDevice>,
+}
+
+impl TryFrom<SonyCommand> for RegisteredDevicesCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        let invalid =
+            || Error::BadFrame(format!("Invalid registered devices payload: {:?}", bytes));
+        if bytes.len() < 3 {
+            return Err(invalid());
+        }
+
+        // Count, then for each device: MAC - 6B, connected - 1B, name length - 1B, name
+        let mut devices = vec![];
+        let mut rest = &bytes[3..];
+        for _ in 0..bytes[2] {
+            if rest.len() < 8 || rest.len() < 8 + rest[7] as usize {
+                return Err(invalid());
+            }
+            let name_end = 8 + rest[7] as usize;
+            devices.push(RegisteredDevice {
+                mac: Mac {
+                    bytes: rest[..6].try_into().unwrap(),
+                },
+                connected: rest[6] != 0,
+                name: String::from_utf8_lossy(&rest[8..name_end]).into_owned(),
+            });
+            rest = &rest[name_end..];
+        }
+        if !rest.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| {
+                    Error::BadFrame(format!("Invalid registered devices command {:?}", x))
+                })?,
+            devices,
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use derive_try_from_primitive::TryFromPrimitive;
+
+use crate::{
+    devices::{DeviceCommand, TouchConfig, TouchFunction},
+    DataType, Error, SonyCommand,
+};
+
+use super::CommandTypes;
+
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum TouchCode {
+    AmbientSoundControl = 0x00,
+    VolumeControl = 0x10,
+    PlaybackControl = 0x20,
+    QuickAccess = 0x35,
+    NotAssigned = 0xff,
+}
+
+impl From<TouchFunction> for TouchCode {
+    fn from(function: TouchFunction) -> Self {
+        match function {
+            TouchFunction::AmbientSoundControl => TouchCode::AmbientSoundControl,
+            TouchFunction::PlaybackControl => TouchCode::PlaybackControl,
+            TouchFunction::VolumeControl => TouchCode::VolumeControl,
+            TouchFunction::QuickAccess => TouchCode::QuickAccess,
+            TouchFunction::NotAssigned => TouchCode::NotAssigned,
+        }
+    }
+}
+
+impl From<TouchCode> for TouchFunction {
+    fn from(code: TouchCode) -> Self {
+        match code {
+            TouchCode::AmbientSoundControl => TouchFunction::AmbientSoundControl,
+            TouchCode::PlaybackControl => TouchFunction::PlaybackControl,
+            TouchCode::VolumeControl => TouchFunction::VolumeControl,
+            TouchCode::QuickAccess => TouchFunction::QuickAccess,
+            TouchCode::NotAssigned => TouchFunction::NotAssigned,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TouchCommand {
+    pub command: CommandTypes,
+    pub left: TouchCode,
+    pub right: TouchCode,
+}
+
+impl From<TouchConfig> for TouchCommand {
+    fn from(config: TouchConfig) -> Self {
+        Self {
+            command: CommandTypes::TouchSet,
+            left: config.left.into(),
+            right: config.right.into(),
+        }
+    }
+}
+
+impl From<TouchCommand> for TouchConfig {
+    fn from(command: TouchCommand) -> Self {
+        Self {
+            left: command.left.into(),
+            right: command.right.into(),
+        }
+    }
+}
+
+impl DeviceCommand for TouchCommand {}
+
+impl TryInto<SonyCommand> for TouchCommand {
+    type Error = Error;
+
+    fn try_into(self) -> Result<SonyCommand, Self::Error> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&(self.command as u16).to_be_bytes());
+        // Number of sensors configured
+        bytes.push(0x02);
+        bytes.push(self.left as u8);
+        bytes.push(self.right as u8);
+
+        Ok(SonyCommand {
+            data_type: DataType::DataMdr,
+            seq_number: 0,
+            payload_size: bytes.len() as u8,
+            payload: bytes,
+            checksum: 0,
+        })
+    }
+}
+
+impl TryFrom<SonyCommand> for TouchCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 5 || bytes[2] != 0x02 {
+            return Err(Error::BadFrame(format!(
+                "Invalid touch payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid touch command {:?}", x)))?,
+            left: bytes[3]
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid touch function {:?}", x)))?,
+            right: bytes[4]
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid touch function {:?}", x)))?,
+        })
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{
+    devices::{wf1000xm4::equalizer::decode_band, Bands, DeviceCommand, Equalizer},
+    DataType, Error, SonyCommand,
+};
+
+use super::CommandTypes;
+
+/// `DataMdr2` equalizer, with Clear Bass followed by 10 bands.
+#[derive(Debug, Clone)]
+pub struct EqualizerCommand {
+    pub command: CommandTypes,
+    pub preset: u8,
+    pub nb_bands: u8,
+    pub bands: Vec<u8>,
+}
+
+impl TryFrom<Equalizer> for EqualizerCommand {
+    type Error = Error;
+
+    fn try_from(equalizer: Equalizer) -> Result<Self, Self::Error> {
+        equalizer.bands.validate()?;
+        let bands = match equalizer.bands {
+            Bands::Zero() => vec![],
+            Bands::TenBandsAndClearBass { clear_bass, bands } => {
+                let mut bytes = vec![(clear_bass + 10) as u8];
+                bytes.extend(bands.iter().map(|band| (band + 10) as u8));
+                bytes
+            }
+            Bands::FiveBandsAndBass { .. } => {
+                return Err(Error::Unsupported(
+                    "This device uses a 10 band equalizer".to_string(),
+                ));
+            }
+        };
+
+        Ok(Self {
+            command: CommandTypes::EqSet,
+            preset: equalizer.profile.into(),
+            nb_bands: bands.len() as u8,
+            bands,
+        })
+    }
+}
+
+impl DeviceCommand for EqualizerCommand {}
+
+impl TryInto<SonyCommand> for EqualizerCommand {
+    type Error = Error;
+
+    fn try_into(self) -> Result<SonyCommand, Self::Error> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&(self.command as u16).to_be_bytes());
+        bytes.push(self.preset);
+        bytes.push(self.nb_bands);
+        bytes.extend_from_slice(&self.bands);
+
+        Ok(SonyCommand {
+            data_type: DataType::DataMdr2,
+            seq_number: 0,
+            payload_size: bytes.len() as u8,
+            payload: bytes,
+            checksum: 0,
+        })
+    }
+}
+
+impl TryFrom<SonyCommand> for EqualizerCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() < 4 || bytes.len() != 4 + bytes[3] as usize {
+            return Err(Error::BadFrame(format!(
+                "Invalid equalizer payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid equalizer command {:?}", x)))?,
+            preset: bytes[2],
+            nb_bands: bytes[3],
+            bands: bytes[4..].to_vec(),
+        })
+    }
+}
+
+impl TryFrom<EqualizerCommand> for Equalizer {
+    type Error = Error;
+
+    fn try_from(command: EqualizerCommand) -> Result<Self, Self::Error> {
+        // Same +10 offset as the `DataMdr` equalizer
+        let bands = command
+            .bands
+            .iter()
+            .map(|b| decode_band(*b))
+            .collect::<Result<Vec<i8>, Error>>()?;
+        let band = |i: usize| bands[i];
+
+        Ok(Self {
+            profile: command.preset.into(),
+            bands: match command.nb_bands {
+                0 => Bands::Zero(),
+                11 => Bands::TenBandsAndClearBass {
+                    clear_bass: band(0),
+                    bands: std::array::from_fn(|i| band(i + 1)),
+                },
+                nb_bands => {
+                    return Err(Error::BadFrame(format!(
+                        "Unsupported number of equalizer bands: {:?}",
+                        nb_bands
+                    )))
+                }
+            },
+        })
+    }
+}
@@ -0,0 +1,37 @@
+use crate::{
+    devices::{wf1000xm4, DeviceEvent, Equalizer},
+    DataType, Error, SonyCommand,
+};
+
+use super::{equalizer::EqualizerCommand, AutoAncCommand, CommandTypes};
+
+fn try_decode(command: SonyCommand) -> Result<Option<DeviceEvent>, Error> {
+    let code = match command.payload.as_slice() {
+        [a, b, ..] => u16::from_be_bytes([*a, *b]),
+        _ => return Ok(None),
+    };
+    let event = match CommandTypes::try_from(code) {
+        Ok(CommandTypes::EqAck) => {
+            let eq: EqualizerCommand = command.try_into()?;
+            DeviceEvent::EqualizerChanged(Equalizer::try_from(eq)?)
+        }
+        Ok(CommandTypes::AutoAncAck) => {
+            let auto_anc: AutoAncCommand = command.try_into()?;
+            DeviceEvent::AutoAncChanged(auto_anc.enable)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+/// Turns a notification into an event, `DataMdr` notifications are decoded
+/// like the ones of a WF-1000XM4.
+pub fn decode_event(command: SonyCommand) -> DeviceEvent {
+    if command.data_type != DataType::DataMdr2 {
+        return wf1000xm4::events::decode_event(command);
+    }
+    match try_decode(command.clone()) {
+        Ok(Some(event)) => event,
+        _ => DeviceEvent::Unknown(command),
+    }
+}
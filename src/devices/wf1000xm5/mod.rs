@@ -0,0 +1,490 @@
+use async_trait::async_trait;
+use bluer::Address;
+use derive_try_from_primitive::TryFromPrimitive;
+use futures::{
+    future::FutureExt,
+    stream::{BoxStream, StreamExt},
+};
+
+use crate::{transport::Transport, DataType, Error, SonyCommand};
+
+use self::{equalizer::EqualizerCommand, events::decode_event};
+
+use super::{
+    unsupported, wf1000xm4::Wf1000xm4, Anc, BatteryInfo, ConnectionQuality, DeviceCommand,
+    DeviceEvent, DeviceFuture, DeviceInfo, DeviceModel, Equalizer, Feature, OnDeviceAnc,
+    ProtocolInfo, RegisteredDevices, SonyDevice, TouchConfig,
+};
+
+pub mod equalizer;
+pub mod events;
+
+pub const MODEL: DeviceModel = DeviceModel {
+    name: "WF-1000XM5",
+    connect,
+    with_transport,
+};
+
+fn connect(mac: Address) -> DeviceFuture {
+    async move { Ok(Box::new(Wf1000xm5::new(mac).await?) as Box<dyn SonyDevice>) }.boxed()
+}
+
+fn with_transport(transport: Box<dyn Transport>) -> Box<dyn SonyDevice> {
+    Box::new(Wf1000xm5::with_transport(transport))
+}
+
+/// Earbuds sharing the `DataMdr` table of the WF-1000XM4, with the
+/// equalizer and automatic noise canceling moved to the `DataMdr2` table.
+///
+/// Firmwares older than [`ProtocolInfo::MDR2`] behave like a WF-1000XM4.
+#[derive(Debug)]
+pub struct Wf1000xm5 {
+    pub(crate) inner: Wf1000xm4,
+    // Negotiated on first use, see `protocol`
+    protocol: Option<ProtocolInfo>,
+}
+
+impl Wf1000xm5 {
+    /// Drive the earbuds over an already established transport, the protocol
+    /// version is negotiated by the first command needing it.
+    pub fn with_transport(transport: impl Transport + 'static) -> Self {
+        Self {
+            inner: Wf1000xm4::with_transport(transport),
+            protocol: None,
+        }
+    }
+
+    /// Protocol version reported by the device, only asked for once.
+    pub(crate) async fn protocol(&mut self) -> Result<ProtocolInfo, Error> {
+        if let Some(protocol) = self.protocol {
+            return Ok(protocol);
+        }
+        let protocol = self.inner.get_protocol_info().await?;
+        self.protocol = Some(protocol);
+        Ok(protocol)
+    }
+
+    /// Sends a `DataMdr2` Get command and returns the first reply carrying one of `replies`.
+    async fn query(
+        &mut self,
+        command: CommandTypes,
+        replies: &[CommandTypes],
+    ) -> Result<SonyCommand, Error> {
+        let replies: Vec<u16> = replies.iter().map(|r| *r as u16).collect();
+        self.inner
+            .conn
+            .request(GetCommand { command }, &replies)
+            .await
+    }
+}
+
+#[async_trait]
+impl SonyDevice for Wf1000xm5 {
+    async fn new(mac: Address) -> Result<Self, Error> {
+        let mut device = Self {
+            inner: Wf1000xm4::new(mac).await?,
+            protocol: None,
+        };
+        device.protocol().await?;
+        Ok(device)
+    }
+
+    async fn supports(&mut self, feature: Feature) -> Result<bool, Error> {
+        match feature {
+            Feature::AutoAnc | Feature::TenBandEqualizer => Ok(self.protocol().await?.has_mdr2()),
+            Feature::SixBandEqualizer => Ok(!self.protocol().await?.has_mdr2()),
+            feature => self.inner.supports(feature).await,
+        }
+    }
+
+    async fn get_protocol_info(&mut self) -> Result<ProtocolInfo, Error> {
+        self.protocol().await
+    }
+
+    async fn get_device_info(&mut self) -> Result<DeviceInfo, Error> {
+        self.inner.get_device_info().await
+    }
+
+    async fn get_battery_info(&mut self) -> Result<BatteryInfo, Error> {
+        self.inner.get_battery_info().await
+    }
+
+    async fn get_registered_devices(&mut self) -> Result<RegisteredDevices, Error> {
+        self.inner.get_registered_devices().await
+    }
+
+    async fn set_anc(&mut self, anc: Anc) -> Result<(), Error> {
+        self.inner.set_anc(anc).await
+    }
+    async fn get_anc(&mut self) -> Result<Anc, Error> {
+        self.inner.get_anc().await
+    }
+
+    async fn set_auto_anc(&mut self, auto_anc: bool) -> Result<(), Error> {
+        if !self.protocol().await?.has_mdr2() {
+            return Err(unsupported("Auto ANC"));
+        }
+        self.inner
+            .conn
+            .send_with_ack(AutoAncCommand {
+                command: CommandTypes::AutoAncSet,
+                enable: auto_anc,
+            })
+            .await
+    }
+    async fn get_auto_anc(&mut self) -> Result<bool, Error> {
+        if !self.protocol().await?.has_mdr2() {
+            return Err(unsupported("Auto ANC"));
+        }
+        let reply = self
+            .query(
+                CommandTypes::AutoAncGet,
+                &[CommandTypes::AutoAncRet, CommandTypes::AutoAncAck],
+            )
+            .await?;
+        let command: AutoAncCommand = reply.try_into()?;
+        Ok(command.enable)
+    }
+
+    async fn set_on_device_anc(&mut self, on_device_anc: OnDeviceAnc) -> Result<(), Error> {
+        self.inner.set_on_device_anc(on_device_anc).await
+    }
+    async fn get_on_device_anc(&mut self) -> Result<OnDeviceAnc, Error> {
+        self.inner.get_on_device_anc().await
+    }
+
+    async fn set_equalizer(&mut self, equalizer: Equalizer) -> Result<(), Error> {
+        if !self.protocol().await?.has_mdr2() {
+            return self.inner.set_equalizer(equalizer).await;
+        }
+        let command: EqualizerCommand = equalizer.try_into()?;
+        self.inner.conn.send_with_ack(command).await
+    }
+    async fn get_equalizer(&mut self) -> Result<Equalizer, Error> {
+        if !self.protocol().await?.has_mdr2() {
+            return self.inner.get_equalizer().await;
+        }
+        let reply = self
+            .query(
+                CommandTypes::EqGet,
+                &[CommandTypes::EqRet, CommandTypes::EqAck],
+            )
+            .await?;
+        let command: EqualizerCommand = reply.try_into()?;
+        command.try_into()
+    }
+
+    async fn set_connection_quality(
+        &mut self,
+        connection_quality: ConnectionQuality,
+    ) -> Result<(), Error> {
+        self.inner.set_connection_quality(connection_quality).await
+    }
+    async fn get_connection_quality(&mut self) -> Result<ConnectionQuality, Error> {
+        self.inner.get_connection_quality().await
+    }
+
+    async fn set_dsee(&mut self, dsee: bool) -> Result<(), Error> {
+        self.inner.set_dsee(dsee).await
+    }
+    async fn get_dsee(&mut self) -> Result<bool, Error> {
+        self.inner.get_dsee().await
+    }
+
+    async fn set_speak_to_chat(&mut self, speak_to_chat: bool) -> Result<(), Error> {
+        self.inner.set_speak_to_chat(speak_to_chat).await
+    }
+    async fn get_speak_to_chat(&mut self) -> Result<bool, Error> {
+        self.inner.get_speak_to_chat().await
+    }
+
+    async fn set_auto_power_off(&mut self, auto_power_off: bool) -> Result<(), Error> {
+        self.inner.set_auto_power_off(auto_power_off).await
+    }
+    async fn get_auto_power_off(&mut self) -> Result<bool, Error> {
+        self.inner.get_auto_power_off().await
+    }
+
+    async fn set_pause_on_remove(&mut self, pause_on_remove: bool) -> Result<(), Error> {
+        self.inner.set_pause_on_remove(pause_on_remove).await
+    }
+    async fn get_pause_on_remove(&mut self) -> Result<bool, Error> {
+        self.inner.get_pause_on_remove().await
+    }
+
+    async fn set_touch_config(&mut self, touch_config: TouchConfig) -> Result<(), Error> {
+        self.inner.set_touch_config(touch_config).await
+    }
+    async fn get_touch_config(&mut self) -> Result<TouchConfig, Error> {
+        self.inner.get_touch_config().await
+    }
+
+    async fn set_bt_multipoint(&mut self, bt_multipoint: bool) -> Result<(), Error> {
+        self.inner.set_bt_multipoint(bt_multipoint).await
+    }
+    async fn get_bt_multipoint(&mut self) -> Result<bool, Error> {
+        self.inner.get_bt_multipoint().await
+    }
+
+    fn events(&self) -> BoxStream<'static, DeviceEvent> {
+        self.inner.conn.notifications().map(decode_event).boxed()
+    }
+}
+
+/// Codes of the `DataMdr2` table, numbered independently from `DataMdr`.
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+#[repr(u16)]
+pub enum CommandTypes {
+    EqGet = 0x5600,
+    EqRet = 0x5700,
+    EqSet = 0x5800,
+    EqAck = 0x5900,
+    AutoAncGet = 0x6620,
+    AutoAncRet = 0x6720,
+    AutoAncSet = 0x6820,
+    AutoAncAck = 0x6920,
+}
+
+/// Query for the current state, answered by the matching Ret command.
+#[derive(Debug, Clone, Copy)]
+struct GetCommand {
+    command: CommandTypes,
+}
+
+impl DeviceCommand for GetCommand {}
+
+impl TryInto<SonyCommand> for GetCommand {
+    type Error = Error;
+
+    fn try_into(self) -> Result<SonyCommand, Self::Error> {
+        let bytes = (self.command as u16).to_be_bytes().to_vec();
+
+        Ok(SonyCommand {
+            data_type: DataType::DataMdr2,
+            seq_number: 0,
+            payload_size: bytes.len() as u8,
+            payload: bytes,
+            checksum: 0,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AutoAncCommand {
+    pub command: CommandTypes,
+    pub enable: bool,
+}
+
+impl DeviceCommand for AutoAncCommand {}
+
+impl TryInto<SonyCommand> for AutoAncCommand {
+    type Error = Error;
+
+    fn try_into(self) -> Result<SonyCommand, Self::Error> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&(self.command as u16).to_be_bytes());
+        bytes.push(self.enable as u8);
+
+        Ok(SonyCommand {
+            data_type: DataType::DataMdr2,
+            seq_number: 0,
+            payload_size: bytes.len() as u8,
+            payload: bytes,
+            checksum: 0,
+        })
+    }
+}
+
+impl TryFrom<SonyCommand> for AutoAncCommand {
+    type Error = Error;
+
+    fn try_from(command: SonyCommand) -> Result<Self, Self::Error> {
+        let bytes = command.payload;
+        if bytes.len() != 3 {
+            return Err(Error::BadFrame(format!(
+                "Invalid auto ANC payload: {:?}",
+                bytes
+            )));
+        }
+
+        Ok(Self {
+            command: u16::from_be_bytes([bytes[0], bytes[1]])
+                .try_into()
+                .map_err(|x| Error::BadFrame(format!("Invalid auto ANC command {:?}", x)))?,
+            enable: bytes[2] != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+    use crate::devices::{
+        wf1000xm4::tests::{headset, headset_frames},
+        Bands, EqualizerProfile,
+    };
+
+    const V1: [u8; 6] = [0x01, 0x00, 0x01, 0x00, 0x00, 0x00];
+    const V2: [u8; 6] = [0x01, 0x00, 0x02, 0x00, 0x00, 0x00];
+
+    #[tokio::test]
+    async fn uses_mdr2_equalizer_from_v2() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset_frames(
+            server,
+            vec![
+                Some(V2.to_vec()),
+                None,
+                Some(vec![
+                    0x57, 0x00, 0xa1, 0x0b, 0x0f, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11,
+                    0x12, 0x00,
+                ]),
+            ],
+        ));
+
+        let mut device = Wf1000xm5::with_transport(client);
+        let equalizer = Equalizer {
+            profile: EqualizerProfile::Custom1,
+            bands: Bands::TenBandsAndClearBass {
+                clear_bass: 5,
+                bands: [0, 1, 2, 3, 4, 5, 6, 7, 8, -9],
+            },
+        };
+        device.set_equalizer(equalizer).await.unwrap();
+        assert_eq!(
+            device.get_equalizer().await.unwrap().bands,
+            Bands::TenBandsAndClearBass {
+                clear_bass: 5,
+                bands: [0, 1, 2, 3, 4, 5, 6, 7, 8, -10],
+            }
+        );
+        drop(device);
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![
+                (DataType::DataMdr, vec![0x00, 0x00]),
+                (
+                    DataType::DataMdr2,
+                    vec![
+                        0x58, 0x00, 0xa1, 0x0b, 0x0f, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10,
+                        0x11, 0x12, 0x01,
+                    ]
+                ),
+                (DataType::DataMdr2, vec![0x56, 0x00]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_mdr_before_v2() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(
+            server,
+            vec![
+                Some(V1.to_vec()),
+                Some(vec![
+                    0x57, 0x00, 0xa2, 0x06, 0x0a, 0x0a, 0x0a, 0x0a, 0x0a, 0x0a,
+                ]),
+            ],
+        ));
+
+        let mut device = Wf1000xm5::with_transport(client);
+        assert!(device.get_auto_anc().await.is_err());
+        assert_eq!(
+            device.get_equalizer().await.unwrap().profile,
+            EqualizerProfile::Custom2
+        );
+        assert_eq!(
+            device.get_protocol_info().await.unwrap(),
+            ProtocolInfo {
+                version: 0x0100_0000
+            }
+        );
+        drop(device);
+        // The handshake is only done once
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![vec![0x00, 0x00], vec![0x56, 0x00]]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_capabilities_of_the_firmware() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(server, vec![Some(V2.to_vec())]));
+
+        let mut device = Wf1000xm5::with_transport(client);
+        assert!(device.supports(Feature::TenBandEqualizer).await.unwrap());
+        assert!(device.supports(Feature::AutoAnc).await.unwrap());
+        assert!(!device.supports(Feature::SixBandEqualizer).await.unwrap());
+        assert!(!device.supports(Feature::NcOptimizer).await.unwrap());
+        drop(device);
+        assert_eq!(headset.await.unwrap(), vec![vec![0x00, 0x00]]);
+    }
+
+    #[tokio::test]
+    async fn sets_auto_anc() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(server, vec![Some(V2.to_vec()), None]));
+
+        let mut device = Wf1000xm5::with_transport(client);
+        device.set_auto_anc(true).await.unwrap();
+        drop(device);
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![vec![0x00, 0x00], vec![0x68, 0x20, 0x01]]
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_equalizer_bands() {
+        let mut payload = vec![0x59, 0x00, 0xa1, 0x0b];
+        payload.extend([0x0a; 10]);
+        payload.push(0xff);
+        let command: EqualizerCommand = SonyCommand {
+            data_type: DataType::DataMdr2,
+            seq_number: 0,
+            payload_size: payload.len() as u8,
+            payload,
+            checksum: 0,
+        }
+        .try_into()
+        .unwrap();
+
+        assert!(matches!(
+            Equalizer::try_from(command),
+            Err(Error::BadFrame(_))
+        ));
+    }
+
+    #[test]
+    fn decodes_events_by_table() {
+        let command = |data_type, payload: Vec<u8>| SonyCommand {
+            data_type,
+            seq_number: 0,
+            payload_size: payload.len() as u8,
+            payload,
+            checksum: 0,
+        };
+
+        assert_eq!(
+            decode_event(command(DataType::DataMdr2, vec![0x69, 0x20, 0x01])),
+            DeviceEvent::AutoAncChanged(true)
+        );
+        // Same code in the `DataMdr` table is the ANC mode, too short here
+        assert!(matches!(
+            decode_event(command(DataType::DataMdr, vec![0x69, 0x20, 0x01])),
+            DeviceEvent::Unknown(_)
+        ));
+        assert_eq!(
+            decode_event(command(
+                DataType::DataMdr,
+                vec![0x69, 0x15, 0x01, 0x00, 0x00, 0x02, 0x00, 0x01]
+            )),
+            DeviceEvent::AncChanged(Anc::Off)
+        );
+    }
+}
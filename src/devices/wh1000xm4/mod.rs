@@ -0,0 +1,221 @@
+use async_trait::async_trait;
+use bluer::Address;
+use futures::{future::FutureExt, stream::BoxStream};
+
+use crate::{transport::Transport, DataType, Error, SonyCommand};
+
+use super::{
+    wf1000xm4::{CommandTypes, Wf1000xm4},
+    Anc, BatteryInfo, ConnectionQuality, DeviceCommand, DeviceEvent, DeviceFuture, DeviceInfo,
+    DeviceModel, Equalizer, Feature, ProtocolInfo, RegisteredDevices, SonyDevice,
+};
+
+pub const MODEL: DeviceModel = DeviceModel {
+    name: "WH-1000XM4",
+    connect,
+    with_transport,
+};
+
+fn connect(mac: Address) -> DeviceFuture {
+    async move { Ok(Box::new(Wh1000xm4::new(mac).await?) as Box<dyn SonyDevice>) }.boxed()
+}
+
+fn with_transport(transport: Box<dyn Transport>) -> Box<dyn SonyDevice> {
+    Box::new(Wh1000xm4::with_transport(transport))
+}
+
+/// Over-ear headphones, speaking the same protocol as the WF-1000XM4 apart
+/// from a single battery, no touch sensor per side and the NC optimizer.
+#[derive(Debug)]
+pub struct Wh1000xm4 {
+    inner: Wf1000xm4,
+}
+
+impl Wh1000xm4 {
+    /// Drive the headphones over an already established transport.
+    pub fn with_transport(transport: impl Transport + 'static) -> Self {
+        Self {
+            inner: Wf1000xm4::with_transport(transport),
+        }
+    }
+}
+
+#[async_trait]
+impl SonyDevice for Wh1000xm4 {
+    async fn new(mac: Address) -> Result<Self, Error> {
+        Ok(Self {
+            inner: Wf1000xm4::new(mac).await?,
+        })
+    }
+
+    async fn supports(&mut self, feature: Feature) -> Result<bool, Error> {
+        match feature {
+            Feature::TouchConfig | Feature::OnDeviceAnc => Ok(false),
+            Feature::NcOptimizer => Ok(true),
+            feature => self.inner.supports(feature).await,
+        }
+    }
+
+    async fn get_protocol_info(&mut self) -> Result<ProtocolInfo, Error> {
+        self.inner.get_protocol_info().await
+    }
+
+    async fn get_device_info(&mut self) -> Result<DeviceInfo, Error> {
+        self.inner.get_device_info().await
+    }
+
+    async fn get_battery_info(&mut self) -> Result<BatteryInfo, Error> {
+        self.inner.get_headphones_battery().await
+    }
+
+    async fn get_registered_devices(&mut self) -> Result<RegisteredDevices, Error> {
+        self.inner.get_registered_devices().await
+    }
+
+    async fn set_anc(&mut self, anc: Anc) -> Result<(), Error> {
+        self.inner.set_anc(anc).await
+    }
+    async fn get_anc(&mut self) -> Result<Anc, Error> {
+        self.inner.get_anc().await
+    }
+
+    async fn optimize_anc(&mut self) -> Result<(), Error> {
+        self.inner
+            .conn
+            .send_with_ack(NcOptimizerCommand {
+                command: CommandTypes::NcOptimizerSet,
+                start: true,
+            })
+            .await
+    }
+
+    async fn set_equalizer(&mut self, equalizer: Equalizer) -> Result<(), Error> {
+        self.inner.set_equalizer(equalizer).await
+    }
+    async fn get_equalizer(&mut self) -> Result<Equalizer, Error> {
+        self.inner.get_equalizer().await
+    }
+
+    async fn set_connection_quality(
+        &mut self,
+        connection_quality: ConnectionQuality,
+    ) -> Result<(), Error> {
+        self.inner.set_connection_quality(connection_quality).await
+    }
+    async fn get_connection_quality(&mut self) -> Result<ConnectionQuality, Error> {
+        self.inner.get_connection_quality().await
+    }
+
+    async fn set_dsee(&mut self, dsee: bool) -> Result<(), Error> {
+        self.inner.set_dsee(dsee).await
+    }
+    async fn get_dsee(&mut self) -> Result<bool, Error> {
+        self.inner.get_dsee().await
+    }
+
+    async fn set_speak_to_chat(&mut self, speak_to_chat: bool) -> Result<(), Error> {
+        self.inner.set_speak_to_chat(speak_to_chat).await
+    }
+    async fn get_speak_to_chat(&mut self) -> Result<bool, Error> {
+        self.inner.get_speak_to_chat().await
+    }
+
+    async fn set_auto_power_off(&mut self, auto_power_off: bool) -> Result<(), Error> {
+        self.inner.set_auto_power_off(auto_power_off).await
+    }
+    async fn get_auto_power_off(&mut self) -> Result<bool, Error> {
+        self.inner.get_auto_power_off().await
+    }
+
+    async fn set_pause_on_remove(&mut self, pause_on_remove: bool) -> Result<(), Error> {
+        self.inner.set_pause_on_remove(pause_on_remove).await
+    }
+    async fn get_pause_on_remove(&mut self) -> Result<bool, Error> {
+        self.inner.get_pause_on_remove().await
+    }
+
+    async fn set_bt_multipoint(&mut self, bt_multipoint: bool) -> Result<(), Error> {
+        self.inner.set_bt_multipoint(bt_multipoint).await
+    }
+    async fn get_bt_multipoint(&mut self) -> Result<bool, Error> {
+        self.inner.get_bt_multipoint().await
+    }
+
+    fn events(&self) -> BoxStream<'static, DeviceEvent> {
+        self.inner.events()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct NcOptimizerCommand {
+    command: CommandTypes,
+    start: bool,
+}
+
+impl DeviceCommand for NcOptimizerCommand {}
+
+impl TryInto<SonyCommand> for NcOptimizerCommand {
+    type Error = Error;
+
+    fn try_into(self) -> Result<SonyCommand, Self::Error> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(&(self.command as u16).to_be_bytes());
+        bytes.push(self.start as u8);
+
+        Ok(SonyCommand {
+            data_type: DataType::DataMdr,
+            seq_number: 0,
+            payload_size: bytes.len() as u8,
+            payload: bytes,
+            checksum: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+    use crate::devices::{wf1000xm4::tests::headset, Battery};
+
+    #[tokio::test]
+    async fn reads_back_single_battery() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(server, vec![Some(vec![0x11, 0x00, 0x3c, 0x01])]));
+
+        let mut device = Wh1000xm4::with_transport(client);
+        assert_eq!(
+            device.get_battery_info().await.unwrap(),
+            BatteryInfo::Headphones(Battery {
+                level: 60,
+                charging: true
+            })
+        );
+        drop(device);
+        assert_eq!(headset.await.unwrap(), vec![vec![0x10, 0x00]]);
+    }
+
+    #[tokio::test]
+    async fn starts_nc_optimizer() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset(server, vec![None]));
+
+        let mut device = Wh1000xm4::with_transport(client);
+        device.optimize_anc().await.unwrap();
+        drop(device);
+        assert_eq!(headset.await.unwrap(), vec![vec![0x84, 0x01, 0x01]]);
+    }
+
+    #[tokio::test]
+    async fn rejects_touch_config() {
+        let (client, _server) = duplex(1024);
+        let mut device = Wh1000xm4::with_transport(client);
+        assert!(!device.supports(Feature::TouchConfig).await.unwrap());
+        assert!(matches!(
+            device.get_touch_config().await,
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+use async_trait::async_trait;
+use bluer::Address;
+use futures::{future::FutureExt, stream::BoxStream};
+
+use crate::{transport::Transport, Error};
+
+use super::{
+    wf1000xm5::Wf1000xm5, Anc, BatteryInfo, ConnectionQuality, DeviceEvent, DeviceFuture,
+    DeviceInfo, DeviceModel, Equalizer, Feature, ProtocolInfo, RegisteredDevices, SonyDevice,
+};
+
+pub const MODEL: DeviceModel = DeviceModel {
+    name: "WH-1000XM5",
+    connect,
+    with_transport,
+};
+
+fn connect(mac: Address) -> DeviceFuture {
+    async move { Ok(Box::new(Wh1000xm5::new(mac).await?) as Box<dyn SonyDevice>) }.boxed()
+}
+
+fn with_transport(transport: Box<dyn Transport>) -> Box<dyn SonyDevice> {
+    Box::new(Wh1000xm5::with_transport(transport))
+}
+
+/// Over-ear headphones speaking the same protocol as the WF-1000XM5, with a
+/// single battery and no configurable touch sensors.
+#[derive(Debug)]
+pub struct Wh1000xm5 {
+    inner: Wf1000xm5,
+}
+
+impl Wh1000xm5 {
+    /// Drive the headphones over an already established transport.
+    pub fn with_transport(transport: impl Transport + 'static) -> Self {
+        Self {
+            inner: Wf1000xm5::with_transport(transport),
+        }
+    }
+}
+
+#[async_trait]
+impl SonyDevice for Wh1000xm5 {
+    async fn new(mac: Address) -> Result<Self, Error> {
+        Ok(Self {
+            inner: Wf1000xm5::new(mac).await?,
+        })
+    }
+
+    async fn supports(&mut self, feature: Feature) -> Result<bool, Error> {
+        match feature {
+            Feature::TouchConfig | Feature::OnDeviceAnc => Ok(false),
+            feature => self.inner.supports(feature).await,
+        }
+    }
+
+    async fn get_protocol_info(&mut self) -> Result<ProtocolInfo, Error> {
+        self.inner.get_protocol_info().await
+    }
+
+    async fn get_device_info(&mut self) -> Result<DeviceInfo, Error> {
+        self.inner.get_device_info().await
+    }
+
+    async fn get_battery_info(&mut self) -> Result<BatteryInfo, Error> {
+        self.inner.inner.get_headphones_battery().await
+    }
+
+    async fn get_registered_devices(&mut self) -> Result<RegisteredDevices, Error> {
+        self.inner.get_registered_devices().await
+    }
+
+    async fn set_anc(&mut self, anc: Anc) -> Result<(), Error> {
+        self.inner.set_anc(anc).await
+    }
+    async fn get_anc(&mut self) -> Result<Anc, Error> {
+        self.inner.get_anc().await
+    }
+
+    async fn set_auto_anc(&mut self, auto_anc: bool) -> Result<(), Error> {
+        self.inner.set_auto_anc(auto_anc).await
+    }
+    async fn get_auto_anc(&mut self) -> Result<bool, Error> {
+        self.inner.get_auto_anc().await
+    }
+
+    async fn set_equalizer(&mut self, equalizer: Equalizer) -> Result<(), Error> {
+        self.inner.set_equalizer(equalizer).await
+    }
+    async fn get_equalizer(&mut self) -> Result<Equalizer, Error> {
+        self.inner.get_equalizer().await
+    }
+
+    async fn set_connection_quality(
+        &mut self,
+        connection_quality: ConnectionQuality,
+    ) -> Result<(), Error> {
+        self.inner.set_connection_quality(connection_quality).await
+    }
+    async fn get_connection_quality(&mut self) -> Result<ConnectionQuality, Error> {
+        self.inner.get_connection_quality().await
+    }
+
+    async fn set_dsee(&mut self, dsee: bool) -> Result<(), Error> {
+        self.inner.set_dsee(dsee).await
+    }
+    async fn get_dsee(&mut self) -> Result<bool, Error> {
+        self.inner.get_dsee().await
+    }
+
+    async fn set_speak_to_chat(&mut self, speak_to_chat: bool) -> Result<(), Error> {
+        self.inner.set_speak_to_chat(speak_to_chat).await
+    }
+    async fn get_speak_to_chat(&mut self) -> Result<bool, Error> {
+        self.inner.get_speak_to_chat().await
+    }
+
+    async fn set_auto_power_off(&mut self, auto_power_off: bool) -> Result<(), Error> {
+        self.inner.set_auto_power_off(auto_power_off).await
+    }
+    async fn get_auto_power_off(&mut self) -> Result<bool, Error> {
+        self.inner.get_auto_power_off().await
+    }
+
+    async fn set_pause_on_remove(&mut self, pause_on_remove: bool) -> Result<(), Error> {
+        self.inner.set_pause_on_remove(pause_on_remove).await
+    }
+    async fn get_pause_on_remove(&mut self) -> Result<bool, Error> {
+        self.inner.get_pause_on_remove().await
+    }
+
+    async fn set_bt_multipoint(&mut self, bt_multipoint: bool) -> Result<(), Error> {
+        self.inner.set_bt_multipoint(bt_multipoint).await
+    }
+    async fn get_bt_multipoint(&mut self) -> Result<bool, Error> {
+        self.inner.get_bt_multipoint().await
+    }
+
+    fn events(&self) -> BoxStream<'static, DeviceEvent> {
+        self.inner.events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+    use crate::{
+        devices::{
+            wf1000xm4::{
+                anc::{AncCommand, AncMode, AsLevel, WindCode},
+                tests::headset_frames,
+                CommandTypes,
+            },
+            wf1000xm5::{self, equalizer::EqualizerCommand},
+            Bands, Battery, EqualizerProfile,
+        },
+        DataType, SonyCommand,
+    };
+
+    const V2: [u8; 6] = [0x01, 0x00, 0x02, 0x00, 0x00, 0x00];
+
+    fn ten_bands() -> Equalizer {
+        Equalizer {
+            profile: EqualizerProfile::Custom2,
+            bands: Bands::TenBandsAndClearBass {
+                clear_bass: -10,
+                bands: [10, -9, 8, -7, 6, -5, 4, -3, 2, 0],
+            },
+        }
+    }
+
+    #[test]
+    fn round_trips_equalizer_commands() {
+        let command: EqualizerCommand = ten_bands().try_into().unwrap();
+        assert_eq!(command.command, wf1000xm5::CommandTypes::EqSet);
+
+        let frame: SonyCommand = command.try_into().unwrap();
+        assert_eq!(frame.data_type, DataType::DataMdr2);
+        let command: EqualizerCommand = frame.try_into().unwrap();
+        assert_eq!(Equalizer::try_from(command).unwrap(), ten_bands());
+    }
+
+    #[test]
+    fn round_trips_anc_commands() {
+        let command = AncCommand {
+            command: CommandTypes::AncSet,
+            continuous: false,
+            anc_enable: true,
+            anc_mode: AncMode::AmbientSound,
+            nc_wind: WindCode::NoWind,
+            as_voice: true,
+            as_level: AsLevel::Level12,
+        };
+
+        let frame: SonyCommand = command.try_into().unwrap();
+        assert_eq!(frame.data_type, DataType::DataMdr);
+        let command: AncCommand = frame.try_into().unwrap();
+        assert_eq!(
+            Anc::from(command),
+            Anc::AmbientSound {
+                level: 12,
+                voice: true
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn sets_and_reads_back_anc_and_equalizer() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset_frames(
+            server,
+            vec![
+                None,
+                Some(vec![0x67, 0x15, 0x01, 0x01, 0x00, 0x03, 0x00, 0x01]),
+                Some(V2.to_vec()),
+                None,
+                Some(vec![
+                    0x57, 0x00, 0xa2, 0x0b, 0x00, 0x14, 0x01, 0x12, 0x03, 0x10, 0x05, 0x0e, 0x07,
+                    0x0c, 0x0a,
+                ]),
+            ],
+        ));
+
+        let mut device = Wh1000xm5::with_transport(client);
+        device
+            .set_anc(Anc::NoiseCanceling { wind: true })
+            .await
+            .unwrap();
+        assert_eq!(
+            device.get_anc().await.unwrap(),
+            Anc::NoiseCanceling { wind: true }
+        );
+        device.set_equalizer(ten_bands()).await.unwrap();
+        assert_eq!(device.get_equalizer().await.unwrap(), ten_bands());
+        drop(device);
+
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![
+                (
+                    DataType::DataMdr,
+                    vec![0x68, 0x15, 0x01, 0x01, 0x00, 0x03, 0x00, 0x01]
+                ),
+                (DataType::DataMdr, vec![0x66, 0x15]),
+                (DataType::DataMdr, vec![0x00, 0x00]),
+                (
+                    DataType::DataMdr2,
+                    vec![
+                        0x58, 0x00, 0xa2, 0x0b, 0x00, 0x14, 0x01, 0x12, 0x03, 0x10, 0x05, 0x0e,
+                        0x07, 0x0c, 0x0a,
+                    ]
+                ),
+                (DataType::DataMdr2, vec![0x56, 0x00]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_back_single_battery() {
+        let (client, server) = duplex(1024);
+        let headset = tokio::spawn(headset_frames(
+            server,
+            vec![Some(vec![0x11, 0x00, 0x46, 0x00])],
+        ));
+
+        let mut device = Wh1000xm5::with_transport(client);
+        assert_eq!(
+            device.get_battery_info().await.unwrap(),
+            BatteryInfo::Headphones(Battery {
+                level: 70,
+                charging: false
+            })
+        );
+        drop(device);
+        assert_eq!(
+            headset.await.unwrap(),
+            vec![(DataType::DataMdr, vec![0x10, 0x00])]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_touch_config() {
+        let (client, _server) = duplex(1024);
+        let mut device = Wh1000xm5::with_transport(client);
+        assert!(!device.supports(Feature::TouchConfig).await.unwrap());
+        assert!(matches!(
+            device.get_touch_config().await,
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
@@ -1,30 +1,64 @@
-#![feature(async_fn_in_trait)]
-
+pub mod decoder;
 pub mod devices;
+pub mod transport;
 
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Display, Formatter};
 
 use derive_try_from_primitive::TryFromPrimitive;
 
-#[derive(Debug)]
-pub struct Error {
-    pub message: String,
+/// Everything that can go wrong while talking to a device.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Reading from or writing to the transport failed, or it was closed.
+    Io(String),
+    /// The device did not ACK or answer a command in time.
+    Timeout(String),
+    /// A frame whose checksum does not match its content.
+    Checksum { expected: u8, actual: u8 },
+    /// A frame or payload that could not be parsed.
+    BadFrame(String),
+    /// A feature the device, or its firmware, does not have.
+    Unsupported(String),
+    /// A value the device would not accept.
+    InvalidArgument(String),
+    /// No paired device matches the one asked for, or its model is unknown.
+    DeviceNotFound(String),
 }
 
-impl Error {
-    pub fn new(message: String) -> Self {
-        Error { message }
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(message) => write!(f, "Connection error: {}", message),
+            Error::Timeout(message) => write!(f, "Device did not answer: {}", message),
+            Error::Checksum { expected, actual } => write!(
+                f,
+                "Checksum mismatch: expected {:#04x}, got {:#04x}",
+                expected, actual
+            ),
+            Error::BadFrame(message) => write!(f, "Bad frame: {}", message),
+            Error::Unsupported(message) => write!(f, "{}", message),
+            Error::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+            Error::DeviceNotFound(message) => write!(f, "{}", message),
+        }
     }
 }
 
-#[derive(Debug, Clone)]
+impl std::error::Error for Error {}
+
+pub const START_MARKER: u8 = b'>';
+pub const END_MARKER: u8 = b'<';
+pub const ESCAPE_BYTE: u8 = b'=';
+const ESCAPE_MASK: u8 = 0b1110_1111;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Mac {
     pub bytes: [u8; 6],
 }
 
-impl ToString for Mac {
-    fn to_string(&self) -> String {
-        format!(
+impl Display for Mac {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
             "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
             self.bytes[0],
             self.bytes[1],
@@ -57,7 +91,7 @@ pub enum DataType {
     LargeData = 0x2d,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SonyCommand {
     pub data_type: DataType,
     pub seq_number: u32,
@@ -72,37 +106,85 @@ impl SonyCommand {
     }
 }
 
+/// Escapes the marker and escape bytes so they never show up inside a frame.
+pub fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+    for b in bytes {
+        match *b {
+            START_MARKER | END_MARKER | ESCAPE_BYTE => {
+                escaped.push(ESCAPE_BYTE);
+                escaped.push(b & ESCAPE_MASK);
+            }
+            _ => escaped.push(*b),
+        }
+    }
+    escaped
+}
+
+/// Reverts [`escape`], failing on a dangling or unknown escape sequence.
+pub fn unescape(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(b) = iter.next() {
+        if *b != ESCAPE_BYTE {
+            unescaped.push(*b);
+            continue;
+        }
+        match iter.next().map(|e| e | !ESCAPE_MASK) {
+            Some(e @ (START_MARKER | END_MARKER | ESCAPE_BYTE)) => unescaped.push(e),
+            _ => {
+                return Err(Error::BadFrame(format!(
+                    "Invalid escape sequence in {:?}",
+                    bytes
+                )));
+            }
+        }
+    }
+    Ok(unescaped)
+}
+
 impl TryFrom<&[u8]> for SonyCommand {
     type Error = Error;
 
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        if bytes.len() < 6 {
-            return Err(Error::new(format!("Invalid Sony Container: {:?}", bytes)));
+        if bytes.len() < 2 || bytes[0] != START_MARKER || bytes[bytes.len() - 1] != END_MARKER {
+            return Err(Error::BadFrame(format!(
+                "Invalid Sony Container: {:?}",
+                bytes
+            )));
         }
 
-        let data_type = bytes[1]
-            .try_into()
-            .map_err(|e: u8| Error::new(format!("Invalid Data Type: {:?}", e)))?;
-        let seq_number = u32::from_le_bytes(bytes[2..6].try_into().unwrap());
-        let payload_size = u8::from_be(bytes[6]) as usize;
-        let payload = bytes[7..7 + payload_size].to_vec();
-        let checksum = bytes[7 + payload_size];
-
-        let mut sum: u8 = data_type as u8 + seq_number as u8 + payload_size as u8;
-        for b in payload.iter() {
-            sum = sum.wrapping_add(*b);
+        // Data type, seq number, payload size, payload and checksum
+        let data = unescape(&bytes[1..bytes.len() - 1])?;
+        if data.len() < 7 || data.len() != 7 + data[5] as usize {
+            return Err(Error::BadFrame(format!(
+                "Invalid Sony Container: {:?}",
+                bytes
+            )));
         }
 
+        let data_type = data[0]
+            .try_into()
+            .map_err(|e: u8| Error::BadFrame(format!("Invalid Data Type: {:?}", e)))?;
+        let seq_number = u32::from_le_bytes(data[1..5].try_into().unwrap());
+        let payload_size = data[5] as usize;
+        let payload = data[6..6 + payload_size].to_vec();
+        let checksum = data[6 + payload_size];
+
+        let sum = data[..6 + payload_size]
+            .iter()
+            .fold(0, |acc: u8, x: &u8| acc.wrapping_add(*x));
+
         if sum != checksum {
-            return Err(Error::new(format!(
-                "Invalid Checksum: {:?} != {:?} for {:?} in {:?}",
-                sum, checksum, payload, bytes
-            )));
+            return Err(Error::Checksum {
+                expected: sum,
+                actual: checksum,
+            });
         }
 
         Ok(SonyCommand {
             seq_number,
-            data_type: data_type,
+            data_type,
             payload_size: payload_size as u8,
             payload,
             checksum,
@@ -114,19 +196,116 @@ impl TryInto<Vec<u8>> for SonyCommand {
     type Error = Error;
 
     fn try_into(self) -> Result<Vec<u8>, Self::Error> {
-        let mut bytes = vec![];
+        let mut data = vec![];
 
-        bytes.push('>' as u8);
-        bytes.push(self.data_type as u8);
-        bytes.extend_from_slice(&self.seq_number.to_le_bytes());
-        bytes.push(self.payload.len() as u8);
-        bytes.append(&mut self.payload.clone());
+        data.push(self.data_type as u8);
+        data.extend_from_slice(&self.seq_number.to_le_bytes());
+        data.push(self.payload.len() as u8);
+        data.extend_from_slice(&self.payload);
 
-        let checksum = bytes[1..]
-            .iter()
-            .fold(0, |acc: u8, x: &u8| acc.wrapping_add(*x));
-        bytes.push(checksum);
-        bytes.push('<' as u8);
+        // Checksum is computed on the unescaped data
+        let checksum = data.iter().fold(0, |acc: u8, x: &u8| acc.wrapping_add(*x));
+        data.push(checksum);
+
+        let mut bytes = vec![START_MARKER];
+        bytes.extend(escape(&data));
+        bytes.push(END_MARKER);
         Ok(bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(payload: &[u8]) -> SonyCommand {
+        SonyCommand {
+            data_type: DataType::DataMdr,
+            seq_number: 0,
+            payload_size: payload.len() as u8,
+            payload: payload.to_vec(),
+            checksum: 0,
+        }
+    }
+
+    fn encode(payload: &[u8]) -> Vec<u8> {
+        command(payload).try_into().unwrap()
+    }
+
+    #[test]
+    fn escapes_every_special_byte() {
+        assert_eq!(escape(&[0x3c]), vec![0x3d, 0x2c]);
+        assert_eq!(escape(&[0x3d]), vec![0x3d, 0x2d]);
+        assert_eq!(escape(&[0x3e]), vec![0x3d, 0x2e]);
+        assert_eq!(escape(&[0x2c, 0x2d, 0x2e]), vec![0x2c, 0x2d, 0x2e]);
+    }
+
+    #[test]
+    fn unescape_reverts_escape() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let escaped = escape(&bytes);
+
+        assert!(!escaped.contains(&START_MARKER));
+        assert!(!escaped.contains(&END_MARKER));
+        assert_eq!(unescape(&escaped).unwrap(), bytes);
+    }
+
+    #[test]
+    fn unescape_rejects_invalid_sequences() {
+        assert!(unescape(&[0x01, 0x3d]).is_err());
+        assert!(unescape(&[0x3d, 0x01]).is_err());
+    }
+
+    #[test]
+    fn encodes_escaped_payload() {
+        let checksum = 0x0cu8 + 0x03 + 0x3c + 0x3d + 0x3e;
+        assert_eq!(
+            encode(&[0x3c, 0x3d, 0x3e]),
+            vec![
+                0x3e, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x03, 0x3d, 0x2c, 0x3d, 0x2d, 0x3d, 0x2e,
+                checksum, 0x3c
+            ]
+        );
+    }
+
+    #[test]
+    fn encodes_escaped_checksum() {
+        // 0x0c + 0x01 + 0x2f == 0x3c
+        assert_eq!(
+            encode(&[0x2f]),
+            vec![0x3e, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x2f, 0x3d, 0x2c, 0x3c]
+        );
+        // 0x0c + 0x01 + 0x30 == 0x3d
+        assert_eq!(
+            encode(&[0x30]),
+            vec![0x3e, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x30, 0x3d, 0x2d, 0x3c]
+        );
+        // 0x0c + 0x01 + 0x31 == 0x3e
+        assert_eq!(
+            encode(&[0x31]),
+            vec![0x3e, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x31, 0x3d, 0x2e, 0x3c]
+        );
+    }
+
+    #[test]
+    fn decodes_escaped_frames() {
+        for payload in [
+            vec![0x3c, 0x3d, 0x3e],
+            vec![0x2f],
+            vec![0x30],
+            vec![0x31],
+            vec![0x3d, 0x3d, 0x2d],
+        ] {
+            let decoded = SonyCommand::try_from(encode(&payload).as_slice()).unwrap();
+            assert_eq!(decoded.payload, payload);
+        }
+    }
+
+    #[test]
+    fn round_trips_every_byte_value() {
+        for b in 0..=255u8 {
+            let decoded = SonyCommand::try_from(encode(&[b, b]).as_slice()).unwrap();
+            assert_eq!(decoded.payload, vec![b, b]);
+        }
+    }
+}
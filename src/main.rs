@@ -1,28 +1,296 @@
 pub mod args;
 
+use std::process::ExitCode;
+
 use args::{Cli, Commands};
+use bluer::Address;
 use clap::Parser;
-use sony_headphone_ctl::devices::{
-    wf1000xm4::Wf1000xm4, Anc, Bands, Equalizer, EqualizerProfile, SonyDevice,
+use futures::StreamExt;
+use serde::Serialize;
+use sony_headphone_ctl::{
+    devices::{
+        connect, find_model, identify, paired_devices, Anc, AncCycle, Bands, BatteryInfo,
+        ConnectionQuality, DeviceStatus, Equalizer, EqualizerProfile, Feature, OnDeviceAnc,
+        PairedDevice, SonyDevice, TouchConfig, TouchFunction,
+    },
+    Error,
 };
 
-async fn process<D: SonyDevice>(args: Cli, mut device: D) {
+fn on_off(enabled: bool) -> String {
+    if enabled { "on" } else { "off" }.to_string()
+}
+
+fn print_status(status: DeviceStatus) {
+    let rows: Vec<(&str, Option<String>)> = vec![
+        (
+            "Model",
+            status.device_info.as_ref().map(|i| i.model_name.clone()),
+        ),
+        (
+            "Firmware",
+            status
+                .device_info
+                .as_ref()
+                .map(|i| i.firmware_version.clone()),
+        ),
+        (
+            "Battery",
+            status.battery.map(|battery| match battery {
+                BatteryInfo::Headphones(battery) => battery.to_string(),
+                BatteryInfo::Earbuds { left, right, case } => {
+                    format!("left {}, right {}, case {}", left, right, case)
+                }
+            }),
+        ),
+        ("ANC", status.anc.map(|anc| format!("{:?}", anc))),
+        ("Auto ANC", status.auto_anc.map(on_off)),
+        (
+            "On device ANC",
+            status.on_device_anc.map(|anc| {
+                format!(
+                    "{:?}, focus on voice {}",
+                    anc.cycle,
+                    on_off(anc.focus_on_voice)
+                )
+            }),
+        ),
+        (
+            "Equalizer",
+            status
+                .equalizer
+                .map(|eq| format!("{:?} {:?}", eq.profile, eq.bands)),
+        ),
+        ("DSEE Extreme", status.dsee.map(on_off)),
+        ("Speak-to-Chat", status.speak_to_chat.map(on_off)),
+        (
+            "Connection quality",
+            status.connection_quality.map(|q| format!("{:?}", q)),
+        ),
+        ("BT multipoint", status.bt_multipoint.map(on_off)),
+        (
+            "Touch",
+            status
+                .touch_config
+                .map(|t| format!("left {:?}, right {:?}", t.left, t.right)),
+        ),
+        ("Auto power off", status.auto_power_off.map(on_off)),
+        ("Wear detection", status.pause_on_remove.map(on_off)),
+    ];
+    for (name, value) in rows {
+        println!(
+            "{:<20}{}",
+            name,
+            value.unwrap_or_else(|| "unknown".to_string())
+        );
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, Error> {
+    serde_json::to_string(value)
+        .map_err(|x| Error::Io(format!("Could not serialize output: {}", x)))
+}
+
+/// Exit code for each kind of error, so scripts can tell them apart.
+fn exit_code(error: &Error) -> u8 {
+    match error {
+        // Same as clap for a bad command line
+        Error::InvalidArgument(_) => 2,
+        Error::Io(_) => 3,
+        Error::Timeout(_) => 4,
+        Error::Checksum { .. } => 5,
+        Error::BadFrame(_) => 6,
+        Error::Unsupported(_) => 7,
+        Error::DeviceNotFound(_) => 8,
+    }
+}
+
+fn print_devices(devices: &[PairedDevice]) {
+    for device in devices {
+        println!(
+            "{}  {:<12}{:<14}{}",
+            device.address,
+            device.model.map_or("unknown", |model| model.name),
+            if device.connected {
+                "connected"
+            } else {
+                "disconnected"
+            },
+            device.alias
+        );
+    }
+}
+
+/// Picks the device matching `wanted`, a MAC address or name, or the only
+/// connected one when nothing is asked for.
+fn select_device(devices: Vec<PairedDevice>, wanted: Option<&str>) -> Result<PairedDevice, Error> {
+    let mut matching: Vec<PairedDevice> = match wanted {
+        Some(wanted) => {
+            let address = wanted.parse::<Address>().ok();
+            devices
+                .into_iter()
+                .filter(|d| Some(d.address) == address || d.alias == wanted)
+                .collect()
+        }
+        // Several paired devices but only one in use is not ambiguous
+        None if devices.iter().filter(|d| d.connected).count() == 1 => {
+            devices.into_iter().filter(|d| d.connected).collect()
+        }
+        None => devices,
+    };
+
+    match matching.len() {
+        1 => Ok(matching.remove(0)),
+        0 => Err(Error::DeviceNotFound(match wanted {
+            Some(wanted) => format!("No supported Sony device matches {}", wanted),
+            None => "No supported Sony device found, see the list command".to_string(),
+        })),
+        _ => {
+            let names: Vec<String> = matching
+                .iter()
+                .map(|d| format!("{} ({})", d.address, d.alias))
+                .collect();
+            Err(Error::InvalidArgument(format!(
+                "Several devices match, pick one with --device: {}",
+                names.join(", ")
+            )))
+        }
+    }
+}
+
+fn touch_function(function: args::TouchFunction) -> TouchFunction {
+    match function {
+        args::TouchFunction::AmbientSoundControl => TouchFunction::AmbientSoundControl,
+        args::TouchFunction::PlaybackControl => TouchFunction::PlaybackControl,
+        args::TouchFunction::VolumeControl => TouchFunction::VolumeControl,
+        args::TouchFunction::QuickAccess => TouchFunction::QuickAccess,
+        args::TouchFunction::NotAssigned => TouchFunction::NotAssigned,
+    }
+}
+
+/// Features the command needs, any one of them is enough.
+fn required_features(command: &Commands) -> &'static [Feature] {
+    match command {
+        Commands::Report(report) => match report {
+            args::Report::Battery => &[Feature::Battery],
+            args::Report::DeviceInfo => &[Feature::DeviceInfo],
+            args::Report::RegisteredDevices => &[Feature::RegisteredDevices],
+            args::Report::Capabilities => &[],
+        },
+        Commands::Config(config) => match config {
+            args::Config::ANC(args::AmbientSoundControl::Optimize) => &[Feature::NcOptimizer],
+            args::Config::ANC(_) => &[Feature::Anc],
+            args::Config::AutoAnc(_) => &[Feature::AutoAnc],
+            args::Config::OnDeviceAnc { .. } => &[Feature::OnDeviceAnc],
+            args::Config::Eq(args::EqualizerControl::Profile { .. }) => {
+                &[Feature::SixBandEqualizer, Feature::TenBandEqualizer]
+            }
+            args::Config::Eq(args::EqualizerControl::SixBand { .. }) => {
+                &[Feature::SixBandEqualizer]
+            }
+            args::Config::Eq(args::EqualizerControl::TenBand { .. }) => {
+                &[Feature::TenBandEqualizer]
+            }
+            args::Config::ConnectionQuality { .. } => &[Feature::ConnectionQuality],
+            args::Config::DSEE(_) => &[Feature::Dsee],
+            args::Config::Stc(_) => &[Feature::SpeakToChat],
+            args::Config::WearDetection(_) => &[Feature::PauseOnRemove],
+            args::Config::AutoPowerOff(_) => &[Feature::AutoPowerOff],
+            args::Config::Multipoint(_) => &[Feature::BtMultipoint],
+            args::Config::Touch { .. } => &[Feature::TouchConfig],
+        },
+        Commands::List | Commands::Status { .. } | Commands::Watch { .. } => &[],
+    }
+}
+
+async fn process(args: Cli, mut device: Box<dyn SonyDevice>) -> Result<(), Error> {
+    // Checked up front so nothing is sent to a device that can't do it
+    let required = required_features(&args.command);
+    let mut supported = required.is_empty();
+    for feature in required {
+        supported = supported || device.supports(*feature).await?;
+    }
+    if !supported {
+        let names: Vec<String> = required.iter().map(ToString::to_string).collect();
+        return Err(Error::Unsupported(format!(
+            "{} is not supported by this device",
+            names.join(" or ")
+        )));
+    }
+
     match args.command {
+        Commands::Report(report) => match report {
+            args::Report::Battery => match device.get_battery_info().await? {
+                BatteryInfo::Headphones(battery) => println!("Battery: {}", battery),
+                BatteryInfo::Earbuds { left, right, case } => {
+                    println!("Left: {}", left);
+                    println!("Right: {}", right);
+                    println!("Case: {}", case);
+                }
+            },
+            args::Report::DeviceInfo => {
+                let info = device.get_device_info().await?;
+                println!("Model: {}", info.model_name);
+                println!("Firmware: {}", info.firmware_version);
+                println!("Series: {:?}", info.series);
+                println!("Colour: {:?}", info.color);
+            }
+            args::Report::Capabilities => {
+                for feature in device.capabilities().await?.features {
+                    println!("{}", feature);
+                }
+            }
+            args::Report::RegisteredDevices => {
+                let registered = device.get_registered_devices().await?;
+                for registered_device in registered.devices {
+                    println!(
+                        "{} {}{}",
+                        registered_device.mac,
+                        registered_device.name,
+                        if registered_device.connected {
+                            " (connected)"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+            }
+        },
         Commands::Config(config) => match config {
             args::Config::ANC(ambient_sound) => match ambient_sound {
                 args::AmbientSoundControl::Ambient { level, voice } => {
-                    device
-                        .set_anc(Anc::AmbientSound { level, voice })
-                        .await
-                        .unwrap();
+                    device.set_anc(Anc::AmbientSound { level, voice }).await?;
                 }
                 args::AmbientSoundControl::NC { wind } => {
-                    device.set_anc(Anc::NoiseCanceling { wind }).await.unwrap();
+                    device.set_anc(Anc::NoiseCanceling { wind }).await?;
                 }
                 args::AmbientSoundControl::Off => {
-                    device.set_anc(Anc::Off).await.unwrap();
+                    device.set_anc(Anc::Off).await?;
+                }
+                args::AmbientSoundControl::Optimize => {
+                    device.optimize_anc().await?;
                 }
             },
+            args::Config::AutoAnc(auto_anc) => match auto_anc {
+                args::Toggle::On => device.set_auto_anc(true).await?,
+                args::Toggle::Off => device.set_auto_anc(false).await?,
+            },
+            args::Config::OnDeviceAnc {
+                cycle,
+                focus_on_voice,
+            } => {
+                let cycle = match cycle {
+                    args::AncCycle::NcAmbientOff => AncCycle::NcAmbientOff,
+                    args::AncCycle::NcAmbient => AncCycle::NcAmbient,
+                    args::AncCycle::NcOff => AncCycle::NcOff,
+                    args::AncCycle::AmbientOff => AncCycle::AmbientOff,
+                };
+                device
+                    .set_on_device_anc(OnDeviceAnc {
+                        cycle,
+                        focus_on_voice,
+                    })
+                    .await?;
+            }
             args::Config::Eq(eq) => match eq {
                 args::EqualizerControl::Profile { profile } => {
                     let eq_profile = match profile {
@@ -35,8 +303,7 @@ async fn process<D: SonyDevice>(args: Cli, mut device: D) {
                             profile: eq_profile,
                             bands: Bands::Zero(),
                         })
-                        .await
-                        .unwrap();
+                        .await?;
                 }
                 args::EqualizerControl::SixBand {
                     profile,
@@ -64,53 +331,252 @@ async fn process<D: SonyDevice>(args: Cli, mut device: D) {
                                 b16k,
                             },
                         })
-                        .await
-                        .unwrap();
+                        .await?;
+                }
+                args::EqualizerControl::TenBand {
+                    profile,
+                    clear_bass,
+                    bands,
+                } => {
+                    let eq_profile = match profile {
+                        args::EqualizerProfile::Off => EqualizerProfile::Off,
+                        args::EqualizerProfile::Custom1 => EqualizerProfile::Custom1,
+                        args::EqualizerProfile::Custom2 => EqualizerProfile::Custom2,
+                    };
+                    device
+                        .set_equalizer(Equalizer {
+                            profile: eq_profile,
+                            bands: Bands::TenBandsAndClearBass {
+                                clear_bass,
+                                bands: bands.try_into().map_err(|_| {
+                                    Error::InvalidArgument("Expected 10 bands".to_string())
+                                })?,
+                            },
+                        })
+                        .await?;
                 }
             },
+            args::Config::ConnectionQuality { mode } => {
+                let connection_quality = match mode {
+                    args::ConnectionQuality::Stable => ConnectionQuality::Stable,
+                    args::ConnectionQuality::Quality => ConnectionQuality::Quality,
+                };
+                device.set_connection_quality(connection_quality).await?;
+            }
             args::Config::DSEE(dsee) => match dsee {
-                args::Toggle::On => device.set_dsee(true).await.unwrap(),
-                args::Toggle::Off => device.set_dsee(false).await.unwrap(),
+                args::Toggle::On => device.set_dsee(true).await?,
+                args::Toggle::Off => device.set_dsee(false).await?,
             },
             args::Config::Stc(stc) => match stc {
-                args::Toggle::On => device.set_speak_to_chat(true).await.unwrap(),
-                args::Toggle::Off => device.set_speak_to_chat(false).await.unwrap(),
+                args::Toggle::On => device.set_speak_to_chat(true).await?,
+                args::Toggle::Off => device.set_speak_to_chat(false).await?,
             },
             args::Config::AutoPowerOff(auto_power_off) => match auto_power_off {
-                args::Toggle::On => device.set_auto_power_off(true).await.unwrap(),
-                args::Toggle::Off => device.set_auto_power_off(false).await.unwrap(),
+                args::Toggle::On => device.set_auto_power_off(true).await?,
+                args::Toggle::Off => device.set_auto_power_off(false).await?,
+            },
+            args::Config::Multipoint(multipoint) => match multipoint {
+                args::Toggle::On => device.set_bt_multipoint(true).await?,
+                args::Toggle::Off => device.set_bt_multipoint(false).await?,
             },
             args::Config::WearDetection(wear_detection) => match wear_detection {
-                args::Toggle::On => device.set_pause_on_remove(true).await.unwrap(),
-                args::Toggle::Off => device.set_pause_on_remove(false).await.unwrap(),
+                args::Toggle::On => device.set_pause_on_remove(true).await?,
+                args::Toggle::Off => device.set_pause_on_remove(false).await?,
             },
-            // _ => {}
+            args::Config::Touch { left, right } => {
+                let touch_config = match (left, right) {
+                    (Some(left), Some(right)) => TouchConfig {
+                        left: touch_function(left),
+                        right: touch_function(right),
+                    },
+                    _ => {
+                        let current = device.get_touch_config().await?;
+                        TouchConfig {
+                            left: left.map(touch_function).unwrap_or(current.left),
+                            right: right.map(touch_function).unwrap_or(current.right),
+                        }
+                    }
+                };
+                device.set_touch_config(touch_config).await?;
+            }
         },
+        // Handled before connecting
+        Commands::List => {}
+        Commands::Status { json } => {
+            let status = DeviceStatus::query(device.as_mut()).await;
+            if json {
+                println!("{}", to_json(&status)?);
+            } else {
+                print_status(status);
+            }
+        }
+        Commands::Watch { json } => {
+            let mut events = device.events();
+            while let Some(event) = events.next().await {
+                if json {
+                    println!("{}", to_json(&event)?);
+                } else {
+                    println!("{}", event);
+                }
+            }
+            // Interrupting the command is the only clean way out
+            return Err(Error::Io("Connection closed".to_string()));
+        }
     }
+    Ok(())
 }
 
 #[tokio::main]
-async fn main() -> bluer::Result<()> {
+async fn main() -> ExitCode {
     let args = args::Cli::parse();
 
-    let session = bluer::Session::new().await?;
-    let adapter = session.default_adapter().await?;
-    adapter.set_powered(true).await?;
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
 
-    let devices_addr = adapter.device_addresses().await?;
+fn bluetooth_error(error: bluer::Error) -> Error {
+    Error::Io(error.to_string())
+}
 
-    for device_addr in devices_addr {
-        let bt_device = adapter.device(device_addr).unwrap();
-        match bt_device.name().await.unwrap() {
-            Some(name) => match name.as_str() {
-                "WF-1000XM4" => {
-                    process(args, Wf1000xm4::new(device_addr).await.unwrap()).await;
-                    break;
-                }
-                _ => {}
-            },
-            _ => {}
+async fn run(args: Cli) -> Result<(), Error> {
+    if let Some(socket) = &args.socket {
+        if args.device.is_some() || args.adapter.is_some() {
+            return Err(Error::InvalidArgument(
+                "--device and --adapter can not be used with --socket".to_string(),
+            ));
+        }
+        let connect = || async {
+            tokio::net::UnixStream::connect(socket)
+                .await
+                .map_err(|x| Error::Io(format!("{}: {}", socket.display(), x)))
+        };
+        let model = match &args.model {
+            Some(name) => find_model(name)
+                .ok_or_else(|| Error::InvalidArgument(format!("Unknown model {}", name)))?,
+            None => identify(connect().await?).await?.ok_or_else(|| {
+                Error::DeviceNotFound(format!("Unsupported model behind {}", socket.display()))
+            })?,
+        };
+        if let Commands::List = args.command {
+            println!("{}  {}  connected", socket.display(), model.name);
+            return Ok(());
         }
+        let stream = connect().await?;
+        return process(args, (model.with_transport)(Box::new(stream))).await;
+    }
+
+    let session = bluer::Session::new().await.map_err(bluetooth_error)?;
+    let adapter = match &args.adapter {
+        Some(name) => {
+            // Getting an adapter does not check that it exists
+            let names = session.adapter_names().await.map_err(bluetooth_error)?;
+            if !names.contains(name) {
+                return Err(Error::DeviceNotFound(format!(
+                    "No adapter named {}, available: {:?}",
+                    name, names
+                )));
+            }
+            session
+                .adapter(name)
+                .map_err(|x| Error::DeviceNotFound(format!("Adapter {}: {}", name, x)))?
+        }
+        None => session.default_adapter().await.map_err(bluetooth_error)?,
+    };
+    adapter.set_powered(true).await.map_err(bluetooth_error)?;
+
+    let devices = paired_devices(&adapter).await?;
+    if let Commands::List = args.command {
+        print_devices(&devices);
+        return Ok(());
+    }
+
+    let device = select_device(devices, args.device.as_deref())?;
+    process(args, connect(&device).await?).await
+}
+
+#[cfg(test)]
+mod tests {
+    use sony_headphone_ctl::devices::MODELS;
+
+    use super::*;
+
+    fn paired(address: &str, alias: &str, connected: bool) -> PairedDevice {
+        PairedDevice {
+            address: address.parse().unwrap(),
+            alias: alias.to_string(),
+            model: Some(&MODELS[0]),
+            connected,
+        }
+    }
+
+    fn devices() -> Vec<PairedDevice> {
+        vec![
+            paired("00:11:22:33:44:55", "Alice's Buds", true),
+            paired("00:11:22:33:44:66", "Bob's Buds", false),
+            paired("00:11:22:33:44:77", "Bob's Buds", false),
+        ]
+    }
+
+    #[test]
+    fn maps_errors_to_exit_codes() {
+        let message = || "message".to_string();
+        let codes = [
+            (Error::InvalidArgument(message()), 2),
+            (Error::Io(message()), 3),
+            (Error::Timeout(message()), 4),
+            (
+                Error::Checksum {
+                    expected: 0x01,
+                    actual: 0x02,
+                },
+                5,
+            ),
+            (Error::BadFrame(message()), 6),
+            (Error::Unsupported(message()), 7),
+            (Error::DeviceNotFound(message()), 8),
+        ];
+        for (error, code) in codes {
+            assert_eq!(exit_code(&error), code, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn selects_device_by_name_or_mac() {
+        let device = select_device(devices(), Some("Alice's Buds")).unwrap();
+        assert_eq!(device.address.to_string(), "00:11:22:33:44:55");
+
+        let device = select_device(devices(), Some("00:11:22:33:44:66")).unwrap();
+        assert_eq!(device.alias, "Bob's Buds");
+    }
+
+    #[test]
+    fn selects_the_only_connected_device() {
+        let device = select_device(devices(), None).unwrap();
+        assert_eq!(device.alias, "Alice's Buds");
+    }
+
+    #[test]
+    fn refuses_missing_devices() {
+        let error = select_device(devices(), Some("Carol's Buds")).unwrap_err();
+        assert!(matches!(error, Error::DeviceNotFound(_)));
+
+        let error = select_device(vec![], None).unwrap_err();
+        assert!(matches!(error, Error::DeviceNotFound(_)));
+    }
+
+    #[test]
+    fn refuses_ambiguous_devices() {
+        let error = select_device(devices(), Some("Bob's Buds")).unwrap_err();
+        assert!(matches!(error, Error::InvalidArgument(_)));
+
+        let mut devices = devices();
+        devices[1].connected = true;
+        let error = select_device(devices, None).unwrap_err();
+        assert!(matches!(error, Error::InvalidArgument(_)));
     }
-    Ok(())
 }
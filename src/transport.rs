@@ -0,0 +1,527 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::{
+    io::{split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf},
+    sync::{broadcast, mpsc, oneshot, Mutex as AsyncMutex},
+    task::JoinHandle,
+    time::timeout,
+};
+
+use crate::{decoder::FrameDecoder, devices::DeviceCommand, DataType, Error, SonyCommand};
+
+/// Byte stream a device can be driven over.
+///
+/// Implemented for anything that is `AsyncRead + AsyncWrite`, so a
+/// `bluer::rfcomm::Stream`, a Unix or TCP socket, or an in-memory
+/// `tokio::io::DuplexStream` can all be used.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send + Debug {}
+
+impl<T> Transport for T where T: AsyncRead + AsyncWrite + Unpin + Send + Debug {}
+
+type Writer = Arc<AsyncMutex<WriteHalf<Box<dyn Transport>>>>;
+
+// Notifications kept for subscribers that are slow to read them
+const NOTIFICATION_CAPACITY: usize = 32;
+
+/// Data frame a pending request is waiting for.
+#[derive(Debug)]
+struct PendingReply {
+    // Codes are only unique within a data type, `DataMdr2` reuses `DataMdr` ones
+    data_type: DataType,
+    replies: Vec<u16>,
+    sender: oneshot::Sender<SonyCommand>,
+}
+
+type Pending = Arc<Mutex<Option<PendingReply>>>;
+
+/// Framed connection to a device on top of a [`Transport`].
+///
+/// A background task reads every frame sent by the device. ACKs are routed
+/// to the command waiting for them, data frames answering a pending request
+/// go to that request and every other data frame is published as a
+/// notification.
+///
+/// Keeps track of the alternating sequence numbers: outgoing commands are
+/// only considered delivered once the ACK for their sequence number comes
+/// back, and retransmits from the device are ACKed again but not published
+/// twice.
+#[derive(Debug)]
+pub struct Connection {
+    writer: Writer,
+    // Sequence number of the next command we send
+    seq_number: u32,
+    // Sequence numbers carried by the ACKs received
+    acks: mpsc::UnboundedReceiver<u32>,
+    pending: Pending,
+    // Never read, only used to hand out new subscriptions
+    notifications: broadcast::Receiver<SonyCommand>,
+    reader: JoinHandle<()>,
+}
+
+/// Sequence number following `seq_number`, which is also the one an ACK for it carries.
+fn next_seq(seq_number: u32) -> u32 {
+    1 - seq_number % 2
+}
+
+async fn write(writer: &Writer, command: SonyCommand) -> Result<(), Error> {
+    let raw_command: Vec<u8> = command.try_into()?;
+    writer
+        .lock()
+        .await
+        .write_all(raw_command.as_slice())
+        .await
+        .map_err(|x| Error::Io(x.to_string()))
+}
+
+/// Acknowledges a data frame received with `seq_number`.
+async fn send_ack(writer: &Writer, seq_number: u32) -> Result<(), Error> {
+    write(
+        writer,
+        SonyCommand {
+            data_type: DataType::Ack,
+            seq_number: next_seq(seq_number),
+            payload_size: 0,
+            payload: vec![],
+            checksum: 0,
+        },
+    )
+    .await
+}
+
+/// Reads frames until the transport is closed, dispatching them.
+async fn read_frames(
+    mut reader: ReadHalf<Box<dyn Transport>>,
+    writer: Writer,
+    acks: mpsc::UnboundedSender<u32>,
+    pending: Pending,
+    notifications: broadcast::Sender<SonyCommand>,
+) -> Result<(), Error> {
+    let mut decoder = FrameDecoder::new();
+    // Sequence number of the last data frame received from the device
+    let mut last_received = None;
+    let mut buffer = vec![0; 1024];
+
+    loop {
+        let len = reader
+            .read(&mut buffer)
+            .await
+            .map_err(|x| Error::Io(x.to_string()))?;
+        if len == 0 {
+            return Err(Error::Io("Connection closed".to_string()));
+        }
+        decoder.push(&buffer[0..len]);
+
+        while let Some(command) = decoder.next_command() {
+            // Frames that fail to parse are dropped, keep going with the next one
+            let Ok(command) = command else {
+                continue;
+            };
+            if command.data_type == DataType::Ack {
+                let _ = acks.send(command.seq_number);
+                continue;
+            }
+
+            send_ack(&writer, command.seq_number).await?;
+            if last_received == Some(command.seq_number) {
+                continue;
+            }
+            last_received = Some(command.seq_number);
+
+            let code = match command.payload.as_slice() {
+                [a, b, ..] => Some(u16::from_be_bytes([*a, *b])),
+                _ => None,
+            };
+            let reply = {
+                let mut pending = pending.lock().unwrap();
+                match (pending.as_ref(), code) {
+                    (Some(p), Some(code))
+                        if p.data_type == command.data_type && p.replies.contains(&code) =>
+                    {
+                        pending.take()
+                    }
+                    _ => None,
+                }
+            };
+            match reply {
+                Some(reply) => {
+                    let _ = reply.sender.send(command);
+                }
+                // Nobody listening is fine, notifications are best effort
+                None => {
+                    let _ = notifications.send(command);
+                }
+            }
+        }
+    }
+}
+
+impl Connection {
+    /// Splits `transport` and starts reading from it in the background.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new(transport: impl Transport + 'static) -> Self {
+        let transport: Box<dyn Transport> = Box::new(transport);
+        let (reader, writer) = split(transport);
+        let writer = Arc::new(AsyncMutex::new(writer));
+        let (acks_sender, acks) = mpsc::unbounded_channel();
+        let pending: Pending = Arc::new(Mutex::new(None));
+        let (notifications_sender, notifications) = broadcast::channel(NOTIFICATION_CAPACITY);
+
+        let reader = tokio::spawn({
+            let writer = writer.clone();
+            let pending = pending.clone();
+            async move {
+                let _ = read_frames(
+                    reader,
+                    writer,
+                    acks_sender,
+                    pending.clone(),
+                    notifications_sender,
+                )
+                .await;
+                // Wake up a request waiting for a reply that will never come
+                pending.lock().unwrap().take();
+            }
+        });
+
+        Self {
+            writer,
+            seq_number: 0,
+            acks,
+            pending,
+            notifications,
+            reader,
+        }
+    }
+
+    /// Sends `command` with the current sequence number, without waiting for the ACK.
+    pub async fn send_command<C: DeviceCommand>(&mut self, command: C) -> Result<(), Error> {
+        let mut command: SonyCommand = command.try_into()?;
+        command.set_seq_number(self.seq_number);
+        write(&self.writer, command).await
+    }
+
+    /// Waits for the ACK of the last command sent.
+    pub async fn wait_ack(&mut self) -> Result<(), Error> {
+        let expected = next_seq(self.seq_number);
+        let wait = async {
+            loop {
+                match self.acks.recv().await {
+                    Some(seq_number) if seq_number == expected => return Ok(()),
+                    // An ACK for an older command, keep waiting for ours
+                    Some(_) => {}
+                    None => return Err(Error::Io("Connection closed".to_string())),
+                }
+            }
+        };
+        match timeout(Duration::from_secs(1), wait).await {
+            Ok(res) => {
+                res?;
+                self.seq_number = expected;
+                Ok(())
+            }
+            Err(_) => Err(Error::Timeout("No Ack received".to_string())),
+        }
+    }
+
+    /// Sends `command` until it is ACKed, retransmitting it with the same
+    /// sequence number so the device can tell it is a duplicate.
+    pub async fn send_with_ack<C: DeviceCommand>(&mut self, command: C) -> Result<(), Error> {
+        // Late ACKs from a previous command would be mistaken for ours
+        while self.acks.try_recv().is_ok() {}
+
+        for _ in 0..3 {
+            self.send_command(command.clone()).await?;
+            if self.wait_ack().await.is_ok() {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout(format!("No Ack received for {:?}", command)))
+    }
+
+    /// Sends `command` and returns the first data frame of the same data type
+    /// whose code is one of `replies`.
+    pub async fn request<C: DeviceCommand>(
+        &mut self,
+        command: C,
+        replies: &[u16],
+    ) -> Result<SonyCommand, Error> {
+        self.try_request(command.clone(), replies)
+            .await?
+            .ok_or_else(|| Error::Timeout(format!("No reply received for {:?}", command)))
+    }
+
+    /// Like [`Connection::request`], but a command that is ACKed and never
+    /// answered gives `None`, which some firmwares do for features they lack.
+    pub async fn try_request<C: DeviceCommand>(
+        &mut self,
+        command: C,
+        replies: &[u16],
+    ) -> Result<Option<SonyCommand>, Error> {
+        let data_type = command.clone().try_into()?.data_type;
+        let (sender, receiver) = oneshot::channel();
+        *self.pending.lock().unwrap() = Some(PendingReply {
+            data_type,
+            replies: replies.to_vec(),
+            sender,
+        });
+
+        let reply = async {
+            self.send_with_ack(command.clone()).await?;
+            match timeout(Duration::from_secs(1), receiver).await {
+                Ok(Ok(reply)) => Ok(Some(reply)),
+                Ok(Err(_)) => Err(Error::Io("Connection closed".to_string())),
+                Err(_) => Ok(None),
+            }
+        }
+        .await;
+        if !matches!(reply, Ok(Some(_))) {
+            self.pending.lock().unwrap().take();
+        }
+        reply
+    }
+
+    /// Stream of the data frames sent by the device that are not replies
+    /// to a request, starting from now.
+    pub fn notifications(&self) -> BoxStream<'static, SonyCommand> {
+        stream::unfold(
+            self.notifications.resubscribe(),
+            |mut receiver| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(command) => return Some((command, receiver)),
+                        // Notifications missed by a slow subscriber are gone
+                        Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, DuplexStream};
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct Raw(DataType, Vec<u8>);
+
+    impl DeviceCommand for Raw {}
+
+    impl TryInto<SonyCommand> for Raw {
+        type Error = Error;
+
+        fn try_into(self) -> Result<SonyCommand, Self::Error> {
+            Ok(SonyCommand {
+                data_type: self.0,
+                seq_number: 0,
+                payload_size: self.1.len() as u8,
+                payload: self.1,
+                checksum: 0,
+            })
+        }
+    }
+
+    fn frame(data_type: DataType, seq_number: u32, payload: &[u8]) -> Vec<u8> {
+        SonyCommand {
+            data_type,
+            seq_number,
+            payload_size: payload.len() as u8,
+            payload: payload.to_vec(),
+            checksum: 0,
+        }
+        .try_into()
+        .unwrap()
+    }
+
+    async fn next_frame(stream: &mut DuplexStream, decoder: &mut FrameDecoder) -> SonyCommand {
+        loop {
+            if let Some(command) = decoder.next_command() {
+                return command.unwrap();
+            }
+            let mut buffer = [0; 1024];
+            let len = stream.read(&mut buffer).await.unwrap();
+            decoder.push(&buffer[..len]);
+        }
+    }
+
+    #[tokio::test]
+    async fn alternates_sequence_numbers() {
+        let (client, mut server) = duplex(1024);
+        let mut conn = Connection::new(client);
+        let headset = tokio::spawn(async move {
+            let mut decoder = FrameDecoder::new();
+            let mut seq_numbers = vec![];
+            for _ in 0..3 {
+                let command = next_frame(&mut server, &mut decoder).await;
+                seq_numbers.push(command.seq_number);
+                let ack = frame(DataType::Ack, 1 - command.seq_number, &[]);
+                server.write_all(&ack).await.unwrap();
+            }
+            (seq_numbers, server)
+        });
+
+        for _ in 0..3 {
+            conn.send_with_ack(Raw(DataType::DataMdr, vec![0x01]))
+                .await
+                .unwrap();
+        }
+
+        let (seq_numbers, _server) = headset.await.unwrap();
+        assert_eq!(seq_numbers, vec![0, 1, 0]);
+    }
+
+    #[tokio::test]
+    async fn ignores_ack_for_another_command() {
+        let (client, mut server) = duplex(1024);
+        let mut conn = Connection::new(client);
+        let headset = tokio::spawn(async move {
+            let mut decoder = FrameDecoder::new();
+            let command = next_frame(&mut server, &mut decoder).await;
+            // Stale ACK first, then the one matching the command
+            server
+                .write_all(&frame(DataType::Ack, command.seq_number, &[]))
+                .await
+                .unwrap();
+            server
+                .write_all(&frame(DataType::Ack, 1 - command.seq_number, &[]))
+                .await
+                .unwrap();
+            server
+        });
+
+        conn.send_with_ack(Raw(DataType::DataMdr, vec![0x01]))
+            .await
+            .unwrap();
+        assert_eq!(conn.seq_number, 1);
+        drop(headset.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn retransmits_with_same_sequence_number() {
+        let (client, mut server) = duplex(1024);
+        let mut conn = Connection::new(client);
+        let headset = tokio::spawn(async move {
+            let mut decoder = FrameDecoder::new();
+            // Drop the first attempt on the floor
+            let first = next_frame(&mut server, &mut decoder).await;
+            let second = next_frame(&mut server, &mut decoder).await;
+            server
+                .write_all(&frame(DataType::Ack, 1 - second.seq_number, &[]))
+                .await
+                .unwrap();
+            (first.seq_number, second.seq_number, server)
+        });
+
+        conn.send_with_ack(Raw(DataType::DataMdr, vec![0x01]))
+            .await
+            .unwrap();
+        let (first, second, _server) = headset.await.unwrap();
+        assert_eq!((first, second), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn acks_and_drops_duplicate_notifications() {
+        let (client, mut server) = duplex(1024);
+        let conn = Connection::new(client);
+        let mut notifications = conn.notifications();
+
+        let mut bytes = frame(DataType::DataMdr, 0, &[0x01]);
+        // Retransmit of the same frame, as if our ACK was lost
+        bytes.extend(frame(DataType::DataMdr, 0, &[0x01]));
+        bytes.extend(frame(DataType::DataMdr, 1, &[0x02]));
+        server.write_all(&bytes).await.unwrap();
+
+        assert_eq!(notifications.next().await.unwrap().payload, vec![0x01]);
+        assert_eq!(notifications.next().await.unwrap().payload, vec![0x02]);
+
+        let mut decoder = FrameDecoder::new();
+        let mut acks = vec![];
+        for _ in 0..3 {
+            let ack = next_frame(&mut server, &mut decoder).await;
+            assert_eq!(ack.data_type, DataType::Ack);
+            acks.push(ack.seq_number);
+        }
+        assert_eq!(acks, vec![1, 1, 0]);
+    }
+
+    #[tokio::test]
+    async fn routes_replies_and_notifications() {
+        let (client, mut server) = duplex(1024);
+        let mut conn = Connection::new(client);
+        let mut notifications = conn.notifications();
+        let headset = tokio::spawn(async move {
+            let mut decoder = FrameDecoder::new();
+            let command = next_frame(&mut server, &mut decoder).await;
+            // Notification sneaking in before the reply and the ACK
+            let mut bytes = frame(DataType::DataMdr, 0, &[0x69, 0x15]);
+            bytes.extend(frame(DataType::DataMdr, 1, &[0x67, 0x15]));
+            bytes.extend(frame(DataType::Ack, 1 - command.seq_number, &[]));
+            server.write_all(&bytes).await.unwrap();
+            server
+        });
+
+        let reply = conn
+            .request(Raw(DataType::DataMdr, vec![0x66, 0x15]), &[0x6715])
+            .await;
+        assert_eq!(reply.unwrap().payload, vec![0x67, 0x15]);
+        assert_eq!(
+            notifications.next().await.unwrap().payload,
+            vec![0x69, 0x15]
+        );
+        drop(headset.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn matches_replies_on_data_type() {
+        let (client, mut server) = duplex(1024);
+        let mut conn = Connection::new(client);
+        let mut notifications = conn.notifications();
+        let headset = tokio::spawn(async move {
+            let mut decoder = FrameDecoder::new();
+            let command = next_frame(&mut server, &mut decoder).await;
+            // Same code in the `DataMdr` table, not the reply
+            let mut bytes = frame(DataType::DataMdr, 0, &[0x57, 0x00, 0x01]);
+            bytes.extend(frame(DataType::DataMdr2, 1, &[0x57, 0x00, 0x02]));
+            bytes.extend(frame(DataType::Ack, 1 - command.seq_number, &[]));
+            server.write_all(&bytes).await.unwrap();
+            server
+        });
+
+        let reply = conn
+            .request(Raw(DataType::DataMdr2, vec![0x56, 0x00]), &[0x5700])
+            .await
+            .unwrap();
+        assert_eq!(reply.data_type, DataType::DataMdr2);
+        assert_eq!(reply.payload, vec![0x57, 0x00, 0x02]);
+        let notification = notifications.next().await.unwrap();
+        assert_eq!(notification.data_type, DataType::DataMdr);
+        drop(headset.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn ends_notifications_when_closed() {
+        let (client, server) = duplex(1024);
+        let conn = Connection::new(client);
+        let mut notifications = conn.notifications();
+
+        drop(server);
+        drop(conn);
+        assert!(notifications.next().await.is_none());
+    }
+}